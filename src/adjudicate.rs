@@ -0,0 +1,212 @@
+use std::str::FromStr;
+
+use crate::engine::Score;
+
+// Rules follow cutechess-cli: both are given as space or comma separated `key=value` pairs,
+// scores are in centipawns from the engine's own point of view.
+
+/// A game is resigned once the loser has reported a score of at most `-score` and the winner
+/// one of at least `score` for `movecount` consecutive moves each
+#[derive(Debug, Clone, Copy)]
+pub struct ResignRule {
+    pub movecount: usize,
+    pub score: i32,
+}
+
+/// A game is drawn once both engines have reported scores within `score` of zero for
+/// `movecount` consecutive moves each, starting from full move `movenumber`
+#[derive(Debug, Clone, Copy)]
+pub struct DrawRule {
+    pub movenumber: usize,
+    pub movecount: usize,
+    pub score: i32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rules {
+    pub resign: Option<ResignRule>,
+    pub draw: Option<DrawRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Resign(chess::Color),
+    Draw,
+}
+
+/// Syzygy WDL tables. Cursed wins and blessed losses are adjudicated as draws, as the 50-move
+/// rule would make them one with best play.
+pub struct Tablebase {
+    tables: shakmaty_syzygy::Tablebase<shakmaty::Chess>,
+}
+
+fn parse_pairs<'a>(s: &'a str, keys: &[&str]) -> Result<Vec<(&'a str, i32)>, String> {
+    s.split([' ', ','])
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (key, value) = p
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, got `{p}`"))?;
+
+            if !keys.contains(&key) {
+                return Err(format!("unknown key `{key}`, expected one of {}", keys.join(", ")));
+            }
+
+            let value = value
+                .parse()
+                .map_err(|_| format!("invalid value for `{key}`: `{value}`"))?;
+
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn get(pairs: &[(&str, i32)], key: &str) -> Result<i32, String> {
+    pairs
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| *v)
+        .ok_or_else(|| format!("missing `{key}`"))
+}
+
+impl FromStr for ResignRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let pairs = parse_pairs(s, &["movecount", "score"])?;
+
+        Ok(Self {
+            movecount: get(&pairs, "movecount")?.max(1) as usize,
+            score: get(&pairs, "score")?.abs(),
+        })
+    }
+}
+
+impl FromStr for DrawRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let pairs = parse_pairs(s, &["movenumber", "movecount", "score"])?;
+
+        Ok(Self {
+            movenumber: get(&pairs, "movenumber")?.max(0) as usize,
+            movecount: get(&pairs, "movecount")?.max(1) as usize,
+            score: get(&pairs, "score")?.abs(),
+        })
+    }
+}
+
+/// Keeps track of the scores reported during one game
+pub struct Adjudicator {
+    rules: Rules,
+    winning: [usize; 2], // consecutive moves with a winning score, w | b
+    losing: [usize; 2],  // consecutive moves with a losing score, w | b
+    drawn: usize,        // consecutive plies with a drawish score
+}
+
+impl Adjudicator {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            winning: [0; 2],
+            losing: [0; 2],
+            drawn: 0,
+        }
+    }
+
+    /// Feeds the score reported by the side that just moved at full move `movenumber`
+    pub fn update(
+        &mut self,
+        color: chess::Color,
+        score: Option<Score>,
+        movenumber: usize,
+    ) -> Option<Verdict> {
+        let side = color.to_index();
+        let cp = score.map(|s| match s {
+            Score::Cp(cp) => cp,
+            Score::Mate(m) if m < 0 => -i32::MAX,
+            Score::Mate(_) => i32::MAX,
+        });
+
+        if let Some(rule) = self.rules.resign {
+            match cp {
+                Some(cp) if cp <= -rule.score => {
+                    self.losing[side] += 1;
+                    self.winning[side] = 0;
+                }
+                Some(cp) if cp >= rule.score => {
+                    self.winning[side] += 1;
+                    self.losing[side] = 0;
+                }
+                _ => {
+                    self.winning[side] = 0;
+                    self.losing[side] = 0;
+                }
+            }
+
+            for loser in [chess::Color::White, chess::Color::Black] {
+                let l = loser.to_index();
+
+                if self.losing[l] >= rule.movecount && self.winning[1 - l] >= rule.movecount {
+                    return Some(Verdict::Resign(loser));
+                }
+            }
+        }
+
+        if let Some(rule) = self.rules.draw {
+            match cp {
+                Some(cp) if movenumber >= rule.movenumber && cp.abs() <= rule.score => {
+                    self.drawn += 1;
+                }
+                _ => self.drawn = 0,
+            }
+
+            if self.drawn >= rule.movecount * 2 {
+                return Some(Verdict::Draw);
+            }
+        }
+
+        None
+    }
+}
+
+impl Tablebase {
+    pub fn open(dir: &str) -> Result<Self, String> {
+        let mut tables = shakmaty_syzygy::Tablebase::new();
+        let n = tables.add_directory(dir).map_err(|e| format!("{dir}: {e}"))?;
+
+        if n == 0 {
+            return Err(format!("{dir}: no Syzygy tables found"));
+        }
+
+        Ok(Self { tables })
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    /// The outcome of `board` with perfect play, if it is covered by the tables. The tables
+    /// assume a capture or pawn move was just played, so wins only count when `halfmoves`, the
+    /// plies since the last one, is 0, as the fifty-move rule could turn them into draws.
+    pub fn probe(&self, board: &chess::Board, halfmoves: usize) -> Option<Verdict> {
+        if board.combined().popcnt() as usize > self.tables.max_pieces() {
+            return None;
+        }
+
+        let pos: shakmaty::Chess = board
+            .to_string()
+            .parse::<shakmaty::fen::Fen>()
+            .ok()?
+            .into_position(shakmaty::CastlingMode::Standard)
+            .ok()?;
+
+        let stm = board.side_to_move();
+        match self.tables.probe_wdl_after_zeroing(&pos).ok()? {
+            shakmaty_syzygy::Wdl::Win | shakmaty_syzygy::Wdl::Loss if halfmoves != 0 => None,
+            shakmaty_syzygy::Wdl::Win => Some(Verdict::Resign(!stm)),
+            shakmaty_syzygy::Wdl::Loss => Some(Verdict::Resign(stm)),
+            _ => Some(Verdict::Draw),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::game::Game;
+
+/// A Polyglot opening book, with its entries sorted by position key as in the file
+pub struct Book {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    key: u64,
+    mv: u16,
+    weight: u16,
+}
+
+impl Book {
+    /// Reads a book of 16-byte big-endian entries: key, move, weight and an unused learn field
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+
+        if bytes.len() % 16 != 0 {
+            return Err(format!("{path}: not a Polyglot book, its size isn't a multiple of 16"));
+        }
+
+        let mut entries = bytes
+            .chunks_exact(16)
+            .map(|e| Entry {
+                key: u64::from_be_bytes(e[..8].try_into().unwrap()),
+                mv: u16::from_be_bytes(e[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(e[10..12].try_into().unwrap()),
+            })
+            .collect::<Vec<_>>();
+
+        // books are meant to be sorted already, but a lookup on an unsorted one finds nothing
+        entries.sort_by_key(|e| e.key);
+
+        Ok(Self { entries })
+    }
+
+    /// A legal book move in the current position of `game`, picked at random by weight
+    pub fn pick(&self, game: &Game, seed: &mut i32) -> Option<chess::ChessMove> {
+        let key = game.polyglot_key();
+        let start = self.entries.partition_point(|e| e.key < key);
+        let moves = self.entries[start..]
+            .iter()
+            .take_while(|e| e.key == key)
+            .filter_map(|e| Some((decode(e.mv)?, e.weight as u64)))
+            .filter(|&(m, _)| game.legal(m))
+            .collect::<Vec<_>>();
+
+        let total = moves.iter().map(|(_, w)| w).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        let mut r = crate::tune::rand(seed) as u32 as u64 % total;
+        moves.into_iter().find_map(|(m, w)| {
+            if r < w {
+                Some(m)
+            } else {
+                r -= w;
+                None
+            }
+        })
+    }
+}
+
+// to file, to row, from file and from row in 3 bits each, then the promotion piece. Castling is
+// the king taking its own rook, which `Game` understands in standard chess too.
+fn decode(mv: u16) -> Option<chess::ChessMove> {
+    let square = |bits: u16| {
+        let file = chess::File::from_index((bits & 7) as usize);
+        let rank = chess::Rank::from_index((bits >> 3 & 7) as usize);
+        chess::Square::make_square(rank, file)
+    };
+
+    let promotion = match mv >> 12 & 7 {
+        0 => None,
+        1 => Some(chess::Piece::Knight),
+        2 => Some(chess::Piece::Bishop),
+        3 => Some(chess::Piece::Rook),
+        4 => Some(chess::Piece::Queen),
+        _ => return None,
+    };
+
+    Some(chess::ChessMove::new(square(mv >> 6), square(mv), promotion))
+}
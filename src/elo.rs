@@ -1,25 +1,92 @@
-use std::sync::*;
+// Maximum likelihood ratings under the BayesElo draw model:
+//   P(a wins) = F(a - b - draw_elo)
+//   P(b wins) = F(b - a - draw_elo)
+//   P(draw)   = 1 - P(a wins) - P(b wins)
+// Only the aggregated results enter the likelihood, so the order games finish in doesn't matter.
 
-pub fn update(r: &Mutex<(f32, f32)>, s_a: f32, s_b: f32) -> (f32, f32, f32, f32) {
-    let mut r = r.lock().unwrap();
+const PRIOR_GAMES: f64 = 1.0;
+const EPSILON: f64 = 0.01;
 
-    let a = r.0;
-    let b = r.1;
+#[derive(Debug, Clone)]
+pub struct Ratings {
+    pub elo: Vec<f64>,
+    pub draw_elo: f64,
+}
+
+/// `results` holds `(a, b, [a wins, draws, b wins])` for every pairing that was played. The
+/// resulting ratings are shifted so that their mean is `mean`.
+pub fn estimate(players: usize, results: &[(usize, usize, [usize; 3])], mean: f64) -> Ratings {
+    // a virtual win and loss per pairing keep the estimate finite when a result never happened
+    let results = results
+        .iter()
+        .map(|&(a, b, [w, d, l])| {
+            (a, b, [w as f64 + PRIOR_GAMES, d as f64, l as f64 + PRIOR_GAMES])
+        })
+        .collect::<Vec<_>>();
+
+    let mut elo = vec![0.0; players];
+    let mut draw_elo = 100.0;
+
+    for _ in 0..1000 {
+        let mut delta: f64 = 0.0;
+
+        for p in 0..players {
+            let old = elo[p];
+            elo[p] = maximize(old - 2000.0, old + 2000.0, |x| {
+                let mut elo = elo.clone();
+                elo[p] = x;
+                log_likelihood(&results, &elo, draw_elo)
+            });
+            delta = delta.max((elo[p] - old).abs());
+        }
+
+        let old = draw_elo;
+        draw_elo = maximize(0.0, 2000.0, |x| log_likelihood(&results, &elo, x));
+        delta = delta.max((draw_elo - old).abs());
 
-    let q_a = 10.0_f32.powf(a / 400.0);
-    let q_b = 10.0_f32.powf(b / 400.0);
-    let e_a = q_a / (q_a + q_b);
-    let e_b = q_b / (q_a + q_b);
+        if delta < EPSILON {
+            break;
+        }
+    }
 
-    let k_a = 2.0 + 36.0 / (1.0 + 2.0_f32.powf((a - 1500.0) / 63.0));
-    let k_b = 2.0 + 36.0 / (1.0 + 2.0_f32.powf((b - 1500.0) / 63.0));
+    let offset = mean - elo.iter().sum::<f64>() / players.max(1) as f64;
+    for e in elo.iter_mut() {
+        *e += offset;
+    }
 
-    let new_a = a + k_a * (s_a - e_a);
-    let new_b = b + k_b * (s_b - e_b);
+    Ratings { elo, draw_elo }
+}
+
+fn log_likelihood(results: &[(usize, usize, [f64; 3])], elo: &[f64], draw_elo: f64) -> f64 {
+    results
+        .iter()
+        .map(|&(a, b, [w, d, l])| {
+            let diff = elo[a] - elo[b];
+            let p_w = f(diff - draw_elo).max(f64::MIN_POSITIVE);
+            let p_l = f(-diff - draw_elo).max(f64::MIN_POSITIVE);
+            let p_d = (1.0 - p_w - p_l).max(f64::MIN_POSITIVE);
+
+            w * p_w.ln() + d * p_d.ln() + l * p_l.ln()
+        })
+        .sum()
+}
+
+fn f(x: f64) -> f64 {
+    1.0 / (1.0 + 10.0_f64.powf(-x / 400.0))
+}
 
-    *r = (new_a, new_b);
+// the likelihood is concave in every single parameter, so ternary search finds the maximum
+fn maximize(mut lo: f64, mut hi: f64, f: impl Fn(f64) -> f64) -> f64 {
+    while hi - lo > EPSILON / 10.0 {
+        let m1 = lo + (hi - lo) / 3.0;
+        let m2 = hi - (hi - lo) / 3.0;
 
-    core::mem::drop(r);
+        if f(m1) < f(m2) {
+            lo = m1;
+        } else {
+            hi = m2;
+        }
+    }
 
-    (a, b, new_a, new_b)
+    (lo + hi) / 2.0
 }
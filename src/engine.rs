@@ -1,162 +1,545 @@
 use std::io::{self, BufRead as _, Write as _};
 use std::process::*;
+use std::sync::mpsc;
 use std::time::*;
 
+#[derive(Debug, Clone)]
+pub struct UciOption {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl std::str::FromStr for UciOption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim().to_string())),
+            None => (s, None),
+        };
+
+        if name.trim().is_empty() {
+            return Err(format!("expected `NAME=VALUE`, got `{s}`"));
+        }
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            value,
+        })
+    }
+}
+
+impl std::fmt::Display for UciOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.value {
+            Some(v) => write!(f, "{}={v}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Cp(i32),
+    Mate(i32),
+}
+
+/// The last complete `info` line an engine sent before its `bestmove`
+#[derive(Debug, Clone, Default)]
+pub struct SearchInfo {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub time: Option<u64>,
+    pub pv: Vec<String>,
+}
+
+impl SearchInfo {
+    /// Parses the tokens after `info`. Lines without a score, such as `currmove` updates, and
+    /// lines for secondary PVs aren't complete results and give `None`.
+    fn parse<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let mut info = Self::default();
+
+        while let Some(t) = tokens.next() {
+            match t {
+                "depth" => info.depth = tokens.next()?.parse().ok(),
+                "seldepth" => info.seldepth = tokens.next()?.parse().ok(),
+                "nodes" => info.nodes = tokens.next()?.parse().ok(),
+                "nps" => info.nps = tokens.next()?.parse().ok(),
+                "time" => info.time = tokens.next()?.parse().ok(),
+                "multipv" => match tokens.next()? {
+                    "1" => {}
+                    _ => return None,
+                },
+                "score" => {
+                    info.score = match tokens.next()? {
+                        "cp" => Some(Score::Cp(tokens.next()?.parse().ok()?)),
+                        "mate" => Some(Score::Mate(tokens.next()?.parse().ok()?)),
+                        _ => None,
+                    };
+                }
+                "lowerbound" | "upperbound" => return None,
+                "pv" => {
+                    info.pv = tokens.by_ref().map(str::to_string).collect();
+                }
+                "string" => return None,
+                _ => {}
+            }
+        }
+
+        info.score.is_some().then_some(info)
+    }
+}
+
+pub struct MoveRecord {
+    pub mv: chess::ChessMove,
+    pub info: SearchInfo,
+    pub time: usize,  // time used in ms
+    pub clock: Option<usize>, // time left after the move in ms
+}
+
+/// Why an engine lost the game on its own move
+#[derive(Debug, Clone)]
+pub enum EngineError {
+    IllegalMove { mv: String, fen: String },
+    /// The engine exited or closed its pipes
+    Disconnected,
+    /// ms past the time it had
+    TimeForfeit(usize),
+    ProtocolError(String),
+}
+
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::IllegalMove { mv, fen } => write!(f, "played the illegal move {mv} in {fen}"),
+            Self::Disconnected => write!(f, "disconnected"),
+            Self::TimeForfeit(overshoot) => write!(f, "lost on time, {overshoot} ms over"),
+            Self::ProtocolError(e) => write!(f, "broke the protocol: {e}"),
+        }
+    }
+}
+
+pub struct EngineInfo {
+    pub name: Option<String>,
+    pub options: Vec<String>,
+}
+
+impl EngineInfo {
+    pub fn check_options(&self, options: &[UciOption]) -> Result<(), String> {
+        for o in options.iter() {
+            if !self.options.iter().any(|a| a.eq_ignore_ascii_case(&o.name)) {
+                return Err(format!(
+                    "unknown option `{}`, the engine supports: {}",
+                    o.name,
+                    self.options.join(", "),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Timestamped transcript of everything sent to and received from an engine, plus its stderr.
+/// Shared with the threads reading the engine's output and switched to a new file every game.
+#[derive(Clone, Default)]
+struct Log(std::sync::Arc<std::sync::Mutex<Option<(std::fs::File, Instant)>>>);
+
+impl Log {
+    fn open(&self, path: Option<&str>) -> Result<(), String> {
+        let file = match path {
+            Some(path) => Some(std::fs::File::create(path).map_err(|e| format!("{path}: {e}"))?),
+            None => None,
+        };
+
+        *self.0.lock().unwrap() = file.map(|f| (f, Instant::now()));
+        Ok(())
+    }
+
+    // `>` is sent to the engine, `<` received from it and `!` its stderr
+    fn write(&self, dir: char, line: &str) {
+        if let Some((file, start)) = self.0.lock().unwrap().as_mut() {
+            let t = start.elapsed().as_secs_f64();
+            _ = writeln!(file, "[{t:10.3}] {dir} {line}");
+        }
+    }
+}
+
+/// How long past its time an engine has to answer before it's considered hung
+const GRACE: Duration = Duration::from_secs(2);
+
 pub struct Engine {
     exec: Child,
+    lines: mpsc::Receiver<String>, // stdout, read on a separate thread
     fen: std::sync::Arc<str>,
+    log: Log,
+    pondering: Option<chess::ChessMove>, // the reply the engine is pondering on
 }
 
-impl<'a> Drop for Engine {
+impl Drop for Engine {
     fn drop(&mut self) {
         _ = self.exec.kill();
+        _ = self.exec.wait();
     }
 }
 
 impl Engine {
-    pub fn get_name(exec: &str) -> Option<String> {
-        let mut exec = std::process::Command::new(exec)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::null())
-            .spawn()
-            .unwrap();
-        writeln!(exec.stdin.as_ref().unwrap(), "uci").unwrap();
-
-        let mut lines = io::BufReader::new(exec.stdout.as_mut().unwrap()).lines();
-        while let Some(Ok(l)) = lines.next() {
-            let mut tokens = l.split_whitespace();
-            let cmd = tokens.next();
+    /// Runs the `uci` handshake to find out the engine's name and options
+    pub fn get_info(exec: &str, timeout: Duration) -> Result<EngineInfo, String> {
+        let (mut child, lines) = spawn(exec, &Log::default())?;
 
-            if matches!(cmd, Some("id")) {
-                if matches!(tokens.next(), Some("name")) {
-                    return Some(l.splitn(3, ' ').nth(2).unwrap().to_string());
-                }
-            } else if matches!(cmd, Some("uciok")) {
-                break;
-            }
-        }
+        let mut info = EngineInfo {
+            name: None,
+            options: Vec::new(),
+        };
 
-        None
+        let handshake = writeln!(child.stdin.as_ref().unwrap(), "uci")
+            .map_err(|e| e.to_string())
+            .and_then(|_| {
+                recv_until(&lines, "uciok", timeout, |l| {
+                    let mut tokens = l.split_whitespace();
+
+                    match tokens.next() {
+                        Some("id") if tokens.next() == Some("name") => {
+                            info.name = Some(l.splitn(3, ' ').nth(2).unwrap_or("").to_string());
+                        }
+                        Some("option") => {
+                            if let Some(name) = option_name(l) {
+                                info.options.push(name.to_string());
+                            }
+                        }
+                        _ => {}
+                    }
+                })
+            });
+
+        _ = child.kill();
+        _ = child.wait();
+
+        handshake
+            .map(|_| info)
+            .map_err(|e| format!("{exec}: no `uciok` after `uci`: {e}"))
     }
 
-    pub fn new(exec: &str, fen: &str) -> Self {
-        let mut exec = std::process::Command::new(exec)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::null())
-            .spawn()
-            .unwrap();
-        writeln!(exec.stdin.as_ref().unwrap(), "uci").unwrap();
-        writeln!(exec.stdin.as_ref().unwrap(), "isready").unwrap();
-        writeln!(exec.stdin.as_ref().unwrap(), "ucinewgame").unwrap();
+    /// Starts an engine for a game from `fen`, logging its traffic to `log` if given. Each step
+    /// of the handshake may take up to `timeout`.
+    pub fn new(
+        exec: &str,
+        fen: &str,
+        options: &[UciOption],
+        log: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let log_path = log;
+        let log = Log::default();
+        log.open(log_path)?;
 
-        for l in io::BufReader::new(exec.stdout.as_mut().unwrap()).lines() {
-            if l.map_or(false, |a| a.starts_with("readyok")) {
-                break;
+        let (exec_child, lines) = spawn(exec, &log)?;
+        let engine = Self {
+            exec: exec_child,
+            lines,
+            fen: fen.into(),
+            log,
+            pondering: None,
+        };
+
+        let uci = "no `uciok` after `uci`";
+        let ready = "no `readyok` after `isready`";
+        let fail = |stage: &str, e: &dyn std::fmt::Display| format!("{exec}: {stage}: {e}");
+
+        engine.send("uci").map_err(|e| fail(uci, &e))?;
+        recv_until(&engine.lines, "uciok", timeout, |_| {}).map_err(|e| fail(uci, &e))?;
+
+        for o in options.iter() {
+            match &o.value {
+                Some(v) => engine.send(&format!("setoption name {} value {v}", o.name)),
+                None => engine.send(&format!("setoption name {}", o.name)),
             }
+            .map_err(|e| fail("setoption", &e))?;
         }
 
-        Self { exec, fen: fen.into() }
+        engine
+            .send("ucinewgame")
+            .and_then(|_| engine.send("isready"))
+            .map_err(|e| fail(ready, &e))?;
+        recv_until(&engine.lines, "readyok", timeout, |_| {}).map_err(|e| fail(ready, &e))?;
+
+        Ok(engine)
     }
 
+    /// Readies an engine left over from an earlier game for a new one from `fen`
+    pub fn new_game(&mut self, fen: &str, log: Option<&str>, timeout: Duration) -> Result<(), String> {
+        if !matches!(self.exec.try_wait(), Ok(None)) {
+            return Err("exited".to_string());
+        }
+
+        self.log.open(log)?;
+        self.fen = fen.into();
+        self.pondering = None;
+
+        // the engine may still be searching if it lost the last game by forfeit
+        ["stop", "ucinewgame", "isready"]
+            .iter()
+            .try_for_each(|l| self.send(l))
+            .map_err(|e| e.to_string())?;
+
+        recv_until(&self.lines, "readyok", timeout, |_| {})
+    }
+
+    fn send(&self, line: &str) -> io::Result<()> {
+        self.log.write('>', line);
+        writeln!(self.exec.stdin.as_ref().unwrap(), "{line}")
+    }
+
+    /// `position` for `game`, followed by `extra` if given
+    fn position(&self, game: &crate::game::Game, extra: Option<chess::ChessMove>) -> String {
+        let moves = game
+            .moves()
+            .iter()
+            .copied()
+            .chain(extra)
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>();
+
+        format!("position fen {} moves {}", self.fen, moves.join(" "))
+    }
+
+    /// Lets the engine move for the side to move in `game`, charging its clock in `clocks`
+    /// (w | b) with up to `margin` ms of leeway. Under a depth or node limit, the engine loses on
+    /// time after `timeout` instead. With `ponder`, the engine then ponders on the reply it
+    /// expects until its next move.
     pub fn get_move(
         &mut self,
-        game: &mut chess::Game,
-        tc: &mut (usize, usize),
-        inc: usize,
-    ) -> Option<chess::ChessMove> {
-        let tc0 = tc.0;
-        let tc1 = tc.1;
-
-        let mt = match game.side_to_move() {
-            chess::Color::White => &mut tc.0,
-            chess::Color::Black => &mut tc.1,
+        game: &mut crate::game::Game,
+        clocks: &mut [crate::tc::Clock; 2],
+        margin: usize,
+        timeout: Duration,
+        ponder: bool,
+    ) -> Result<MoveRecord, EngineError> {
+        let stm = game.side_to_move();
+        let last = game.moves().last().copied();
+
+        let start = match self.pondering.take() {
+            // the engine's clock only starts running once its guess is confirmed
+            Some(p) if Some(p) == last => {
+                let start = Instant::now();
+                self.send("ponderhit").map_err(|_| EngineError::Disconnected)?;
+                start
+            }
+            pondering => {
+                if pondering.is_some() {
+                    self.send("stop").map_err(|_| EngineError::Disconnected)?;
+                    recv_until(&self.lines, "bestmove", GRACE, |_| {}).map_err(|e| {
+                        EngineError::ProtocolError(format!("no `bestmove` after `stop` while pondering: {e}"))
+                    })?;
+                }
+
+                self.send(&self.position(game, None)).map_err(|_| EngineError::Disconnected)?;
+                let start = Instant::now();
+                self.send(&crate::tc::go(clocks, stm)).map_err(|_| EngineError::Disconnected)?;
+                start
+            }
         };
 
-        writeln!(
-            self.exec.stdin.as_ref().unwrap(),
-            "position fen {} moves {}",
-            self.fen,
-            game.actions()
-                .iter()
-                .map(|a| match a {
-                    chess::Action::MakeMove(m) => m.to_string(),
-                    _ => "".to_string(),
-                })
-                .collect::<Vec<String>>()
-                .join(" ")
-        )
-        .unwrap();
-
-        let start = Instant::now();
-
-        writeln!(
-            self.exec.stdin.as_ref().unwrap(),
-            "go wtime {} winc {inc} btime {} binc {inc}",
-            tc0,
-            tc1
-        )
-        .unwrap();
-
-        self.find_best_in_time().map_or(None, |m| {
+        let clock = &mut clocks[stm.to_index()];
+        // a hung engine would stall the game forever without a limit even when the time control
+        // has none
+        let available = clock.available().unwrap_or(timeout.as_millis() as usize);
+        let deadline = Duration::from_millis((available + margin) as u64) + GRACE;
+
+        let Some((mv, ponder_mv, info)) = self.find_best_in_time(start, clock.cap(), deadline)? else {
+            // by how much it overshot its clock, as `Clock::spend` reports it too
             let used_time = start.elapsed().as_millis() as usize;
+            return Err(EngineError::TimeForfeit(used_time.saturating_sub(available)));
+        };
+        let used_time = start.elapsed().as_millis() as usize;
 
-            if !game.current_position().legal(m) {
-                return None;
-            }
+        let m = move_from_uci(&mv)
+            .ok_or_else(|| EngineError::ProtocolError(format!("invalid move `{mv}`")))?;
 
-            game.make_move(m);
+        if !game.legal(m) {
+            return Err(EngineError::IllegalMove { mv, fen: game.fen() });
+        }
 
-            let time = (*mt + inc).checked_sub(used_time);
-            if let Some(time) = time {
-                *mt = time;
-                Some(m)
-            } else {
-                None
-            }
+        clock.spend(used_time, margin).map_err(EngineError::TimeForfeit)?;
+        let left = clock.left();
+        game.make_move(m);
+        let m = *game.moves().last().unwrap();
+
+        // a ponder move that isn't legal is ignored rather than forfeited, since it is only a hint
+        let reply = ponder_mv
+            .as_deref()
+            .and_then(move_from_uci)
+            .filter(|&p| ponder && game.legal(p));
+
+        if let Some(p) = reply {
+            let go = crate::tc::go(clocks, stm);
+
+            self.send(&self.position(game, Some(p)))
+                .and_then(|_| self.send(&format!("go ponder{}", &go[2..])))
+                .map_err(|_| EngineError::Disconnected)?;
+            self.pondering = Some(p);
+        }
+
+        Ok(MoveRecord {
+            mv: m,
+            info,
+            time: used_time,
+            clock: left,
         })
     }
 
-    fn find_best_in_time(&mut self) -> Option<chess::ChessMove> {
-        let mut lines = io::BufReader::new(self.exec.stdout.as_mut().unwrap()).lines();
-        while let Some(Ok(l)) = lines.next() {
+    /// Reads up to the `bestmove` of a search started at `start`, sending `stop` once `cap` has
+    /// passed, and gives the move with the `ponder` move if any. An engine still searching at
+    /// `deadline` is stopped and killed, giving `None`.
+    #[allow(clippy::type_complexity)]
+    fn find_best_in_time(
+        &mut self,
+        start: Instant,
+        cap: Option<Duration>,
+        deadline: Duration,
+    ) -> Result<Option<(String, Option<String>, SearchInfo)>, EngineError> {
+        let mut info = SearchInfo::default();
+        let mut cap = cap;
+
+        loop {
+            let wait = cap.unwrap_or(deadline).saturating_sub(start.elapsed());
+
+            let l = match self.lines.recv_timeout(wait) {
+                Ok(l) => l,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(EngineError::Disconnected),
+                Err(mpsc::RecvTimeoutError::Timeout) if cap.is_some() => {
+                    cap = None;
+                    _ = self.send("stop");
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    _ = self.send("stop");
+                    _ = self.exec.kill();
+                    return Ok(None);
+                }
+            };
+
             let mut tokens = l.split_whitespace();
-            if matches!(tokens.next(), Some("bestmove")) {
-                return Some(move_from_uci(&tokens.next().unwrap()));
+            match tokens.next() {
+                Some("bestmove") => {
+                    let mv = tokens.next();
+                    let ponder = match (tokens.next(), tokens.next()) {
+                        (Some("ponder"), Some(p)) => Some(p.to_string()),
+                        _ => None,
+                    };
+
+                    return match mv {
+                        Some(mv) => Ok(Some((mv.to_string(), ponder, info))),
+                        None => Err(EngineError::ProtocolError("`bestmove` without a move".to_string())),
+                    };
+                }
+                Some("info") => {
+                    if let Some(i) = SearchInfo::parse(tokens) {
+                        info = i;
+                    }
+                }
+                _ => {}
             }
         }
-
-        None
     }
 
     pub fn send_features(&mut self, features: &crate::tune::FeatureVector<i32>) {
-        write!(self.exec.stdin.as_mut().unwrap(), "settraindata ").unwrap();
+        let mut line = String::from("settraindata ");
         for i in features.iter() {
-            write!(self.exec.stdin.as_mut().unwrap(), "{i} ").unwrap();
+            line += &format!("{i} ");
         }
-        writeln!(self.exec.stdin.as_mut().unwrap()).unwrap();
+        self.send(&line).unwrap();
     }
 }
 
-fn move_from_uci(m: &str) -> chess::ChessMove {
-    let src = &m[0..2];
-    let src = unsafe {
-        chess::Square::new(((src.as_bytes()[1] - b'1') << 3) + (src.as_bytes()[0] - b'a'))
-    };
+/// Spawns `exec` with its stdout read line by line on a separate thread
+fn spawn(exec: &str, log: &Log) -> Result<(Child, mpsc::Receiver<String>), String> {
+    let mut child = std::process::Command::new(exec)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{exec}: failed to start: {e}"))?;
 
-    let dst = &m[2..4];
-    let dst = unsafe {
-        chess::Square::new(((dst.as_bytes()[1] - b'1') << 3) + (dst.as_bytes()[0] - b'a'))
-    };
+    if let Some(stderr) = child.stderr.take() {
+        let log = log.clone();
 
-    let piece = m.as_bytes().get(4).and_then(|p| match p {
-        b'n' => Some(chess::Piece::Knight),
-        b'b' => Some(chess::Piece::Bishop),
-        b'q' => Some(chess::Piece::Queen),
-        b'r' => Some(chess::Piece::Rook),
-        _ => None,
+        std::thread::spawn(move || {
+            for l in io::BufReader::new(stderr).lines().map_while(Result::ok) {
+                log.write('!', &l);
+            }
+        });
+    }
+
+    let stdout = child.stdout.take().unwrap();
+    let (tx, lines) = mpsc::channel();
+    let log = log.clone();
+
+    std::thread::spawn(move || {
+        for l in io::BufReader::new(stdout).lines().map_while(Result::ok) {
+            log.write('<', &l);
+
+            if tx.send(l).is_err() {
+                break;
+            }
+        }
     });
 
-    chess::ChessMove::new(src, dst, piece)
+    Ok((child, lines))
+}
+
+/// Waits for a line starting with `token`, passing the ones before it to `on_line`
+fn recv_until(
+    lines: &mpsc::Receiver<String>,
+    token: &str,
+    timeout: Duration,
+    mut on_line: impl FnMut(&str),
+) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        match lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(l) if l.starts_with(token) => return Ok(()),
+            Ok(l) => on_line(&l),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                return Err(format!("timed out after {:.1}s", timeout.as_secs_f64()));
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err("exited".to_string()),
+        }
+    }
+}
+
+// `option name <name> type <type> ...`, where the name may contain spaces
+fn option_name(l: &str) -> Option<&str> {
+    let l = l.trim_start().strip_prefix("option")?.trim_start().strip_prefix("name ")?;
+    let end = l.find(" type ").unwrap_or(l.len());
+
+    Some(l[..end].trim())
+}
+
+pub fn move_from_uci(m: &str) -> Option<chess::ChessMove> {
+    let square = |s: &[u8]| match s {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some(unsafe {
+            chess::Square::new(((rank - b'1') << 3) + (file - b'a'))
+        }),
+        _ => None,
+    };
+
+    let src = square(m.as_bytes().get(0..2)?)?;
+    let dst = square(m.as_bytes().get(2..4)?)?;
+
+    let piece = match m.as_bytes().get(4..) {
+        Some([]) => None,
+        Some([b'n']) => Some(chess::Piece::Knight),
+        Some([b'b']) => Some(chess::Piece::Bishop),
+        Some([b'q']) => Some(chess::Piece::Queen),
+        Some([b'r']) => Some(chess::Piece::Rook),
+        _ => return None,
+    };
+
+    Some(chess::ChessMove::new(src, dst, piece))
 }
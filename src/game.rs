@@ -0,0 +1,253 @@
+use std::str::FromStr;
+
+use shakmaty::Position as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    Chess960,
+}
+
+impl Variant {
+    fn castling_mode(self) -> shakmaty::CastlingMode {
+        match self {
+            Self::Standard => shakmaty::CastlingMode::Standard,
+            Self::Chess960 => shakmaty::CastlingMode::Chess960,
+        }
+    }
+
+    /// A line of an openings file as a FEN. In Chess960, a number from 0 to 959 stands for that
+    /// start position.
+    pub fn opening(self, line: &str) -> String {
+        match (self, line.trim().parse()) {
+            (Self::Chess960, Ok(n)) if n < 960 => chess960(n),
+            _ => line.to_string(),
+        }
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "standard" => Ok(Self::Standard),
+            "chess960" => Ok(Self::Chess960),
+            _ => Err(format!("unknown variant `{s}`, expected standard or chess960")),
+        }
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Standard => write!(f, "standard"),
+            Self::Chess960 => write!(f, "chess960"),
+        }
+    }
+}
+
+/// Chess960 start position number `n` in Scharnagl's numbering, with X-FEN castling rights.
+/// Position 518 is the standard one.
+pub fn chess960(n: usize) -> String {
+    let mut rank = [None; 8];
+
+    // the light-squared bishop, then the dark-squared one
+    rank[n % 4 * 2 + 1] = Some('B');
+    rank[n / 4 % 4 * 2] = Some('B');
+
+    let mut place = |piece, nth: usize| {
+        let file = (0..8).filter(|&f| rank[f].is_none()).nth(nth).unwrap();
+        rank[file] = Some(piece);
+    };
+
+    place('Q', n / 16 % 6);
+
+    // the second knight is counted among the squares left after the first is placed
+    let (n1, n2) = [(0, 0), (0, 1), (0, 2), (0, 3), (1, 0), (1, 1), (1, 2), (2, 0), (2, 1), (3, 0)][n / 96];
+    place('N', n1);
+    place('N', n1 + n2);
+
+    for piece in ['R', 'K', 'R'] {
+        place(piece, 0);
+    }
+
+    let white = rank.iter().map(|p| p.unwrap()).collect::<String>();
+
+    format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{white} w KQkq - 0 1", white.to_lowercase())
+}
+
+/// A game from any start position. The `chess` crate only knows standard castling, so the rules
+/// are left to `shakmaty` and positions are handed out as `chess::Board`s, which lack the
+/// castling rights in Chess960.
+#[derive(Clone)]
+pub struct Game {
+    variant: Variant,
+    start: shakmaty::Chess,
+    pos: shakmaty::Chess,
+    moves: Vec<chess::ChessMove>, // in UCI notation of the variant, king takes rook in Chess960
+    result: Option<chess::GameResult>, // by resignation or agreement
+}
+
+impl Game {
+    pub fn new(fen: &str, variant: Variant) -> Result<Self, String> {
+        let pos: shakmaty::Chess = fen
+            .parse::<shakmaty::fen::Fen>()
+            .map_err(|e| format!("invalid FEN `{fen}`: {e}"))?
+            .into_position(variant.castling_mode())
+            .map_err(|e| format!("invalid {variant} position `{fen}`: {e}"))?;
+
+        Ok(Self {
+            variant,
+            start: pos.clone(),
+            pos,
+            moves: Vec::new(),
+            result: None,
+        })
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// The same game without any moves played
+    pub fn restart(&self) -> Self {
+        Self {
+            variant: self.variant,
+            start: self.start.clone(),
+            pos: self.start.clone(),
+            moves: Vec::new(),
+            result: None,
+        }
+    }
+
+    pub fn side_to_move(&self) -> chess::Color {
+        match self.pos.turn() {
+            shakmaty::Color::White => chess::Color::White,
+            shakmaty::Color::Black => chess::Color::Black,
+        }
+    }
+
+    /// X-FEN of the current position
+    pub fn fen(&self) -> String {
+        shakmaty::fen::Fen::from_position(&self.pos, shakmaty::EnPassantMode::Legal).to_string()
+    }
+
+    pub fn current_position(&self) -> chess::Board {
+        let fen = self.fen();
+
+        match self.variant {
+            Variant::Standard => chess::Board::from_str(&fen).unwrap(),
+            Variant::Chess960 => {
+                let mut fields = fen.split(' ').collect::<Vec<_>>();
+                fields[2] = "-";
+                chess::Board::from_str(&fields.join(" ")).unwrap()
+            }
+        }
+    }
+
+    pub fn moves(&self) -> &[chess::ChessMove] {
+        &self.moves
+    }
+
+    /// Hash of the current position including castling rights, for finding repetitions
+    pub fn hash(&self) -> u64 {
+        self.pos
+            .zobrist_hash::<shakmaty::zobrist::Zobrist64>(shakmaty::EnPassantMode::Legal)
+            .0
+    }
+
+    /// Key of the current position in a Polyglot book. Polyglot only counts an en passant square
+    /// when a pawn stands next to the pawn that just moved, legal capture or not.
+    pub fn polyglot_key(&self) -> u64 {
+        self.pos
+            .zobrist_hash::<shakmaty::zobrist::Zobrist64>(shakmaty::EnPassantMode::PseudoLegal)
+            .0
+    }
+
+    // castling may be given either as the king moving two squares or as it taking its rook
+    fn to_move(&self, m: chess::ChessMove) -> Option<shakmaty::Move> {
+        let uci: shakmaty::uci::UciMove = m.to_string().parse().ok()?;
+        uci.to_move(&self.pos).ok()
+    }
+
+    pub fn legal(&self, m: chess::ChessMove) -> bool {
+        self.to_move(m).is_some()
+    }
+
+    pub fn make_move(&mut self, m: chess::ChessMove) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+
+        let Some(m) = self.to_move(m) else {
+            return false;
+        };
+
+        let uci = shakmaty::uci::UciMove::from_move(m, self.variant.castling_mode());
+        self.moves.push(crate::engine::move_from_uci(&uci.to_string()).unwrap());
+        self.pos.play_unchecked(m);
+
+        true
+    }
+
+    pub fn resign(&mut self, color: chess::Color) {
+        self.result = Some(match color {
+            chess::Color::White => chess::GameResult::WhiteResigns,
+            chess::Color::Black => chess::GameResult::BlackResigns,
+        });
+    }
+
+    pub fn draw(&mut self) {
+        self.result = Some(chess::GameResult::DrawAccepted);
+    }
+
+    pub fn result(&self) -> Option<chess::GameResult> {
+        if self.result.is_some() {
+            self.result
+        } else if self.pos.is_checkmate() {
+            Some(match self.side_to_move() {
+                chess::Color::White => chess::GameResult::BlackCheckmates,
+                chess::Color::Black => chess::GameResult::WhiteCheckmates,
+            })
+        } else if self.pos.is_stalemate() {
+            Some(chess::GameResult::Stalemate)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scharnagl_numbering() {
+        assert_eq!(chess960(0), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+        assert_eq!(chess960(518), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(chess960(959), "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w KQkq - 0 1");
+    }
+
+    #[test]
+    fn castling_in_chess960() {
+        // the king taking its own rook, which leaves the king where it is
+        let mut game = Game::new("4k3/8/8/8/8/8/8/R5KR w HA - 0 1", Variant::Chess960).unwrap();
+        assert!(game.make_move(crate::engine::move_from_uci("g1h1").unwrap()));
+        assert_eq!(game.fen(), "4k3/8/8/8/8/8/8/R4RK1 b - - 1 1");
+
+        // either form is accepted, and recorded as the variant writes it in UCI
+        let fen = "4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+
+        for (variant, given, recorded) in [
+            (Variant::Standard, "e1h1", "e1g1"),
+            (Variant::Chess960, "e1g1", "e1h1"),
+        ] {
+            let mut game = Game::new(fen, variant).unwrap();
+            assert!(game.make_move(crate::engine::move_from_uci(given).unwrap()));
+            assert_eq!(game.moves()[0].to_string(), recorded);
+            assert_eq!(game.fen(), "4k3/8/8/8/8/8/8/R4RK1 b - - 1 1");
+        }
+    }
+}
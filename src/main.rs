@@ -1,12 +1,22 @@
 use clap::*;
 use std::str::FromStr;
-use std::sync::{atomic::*, *};
+use std::sync::*;
 
+mod adjudicate;
+mod book;
 mod elo;
 mod engine;
+mod game;
+mod openings;
 mod pgn;
-mod tune;
+mod pool;
 mod render;
+mod sprt;
+mod state;
+mod stats;
+mod tc;
+mod tournament;
+mod tune;
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -17,21 +27,158 @@ struct Args {
 #[derive(Debug, Subcommand)]
 enum Command {
     Play(PlayArgs),
+    Tournament(TournamentArgs),
     Tune(TuneArgs),
     Watch(WatchArgs),
 }
 
 #[derive(Debug, Args)]
-struct PlayArgs {
-    a: String,
-    b: String,
+struct GameArgs {
+    /// Resign adjudication, e.g. `--resign movecount=3 score=700`
+    #[arg(long, num_args = 1..=2, value_name = "KEY=VALUE")]
+    resign: Vec<String>,
 
-    time: usize,
-    inc: usize,
+    /// Draw adjudication, e.g. `--draw movenumber=40 movecount=8 score=10`
+    #[arg(long, num_args = 1..=3, value_name = "KEY=VALUE")]
+    draw: Vec<String>,
+
+    /// Time control such as `40/60+0.6`, `st=0.1`, `depth=8`, `nodes=10000` or `inf=30` (a hard
+    /// cap in seconds), overriding TIME and INC
+    #[arg(long)]
+    tc: Option<tc::TimeControl>,
+
+    /// How many ms an engine may exceed its clock by before it loses on time
+    #[arg(long, default_value_t = 0, value_name = "MS")]
+    timemargin: usize,
+
+    /// Write a transcript of each engine's UCI traffic and stderr for every game into DIR
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<String>,
+
+    /// How long an engine may take to answer `uci` and `isready`
+    #[arg(long, default_value_t = 10000, value_name = "MS")]
+    handshake_timeout: u64,
+
+    /// How long an engine may think on a move under a depth or node limit before it loses on time
+    #[arg(long, default_value_t = 60000, value_name = "MS")]
+    move_timeout: u64,
+
+    /// Start new engine processes for every game instead of reusing them between games
+    #[arg(long, action = ArgAction::SetTrue)]
+    fresh_engines: bool,
+
+    /// Let engines think on their expected reply during the opponent's turn
+    #[arg(long, action = ArgAction::SetTrue)]
+    ponder: bool,
 
-    #[arg(long, default_value = "openings.txt")]
+    /// Only draw by fivefold repetition and the seventy-five-move rule instead of as soon as a
+    /// draw could be claimed by threefold repetition or the fifty-move rule
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_draw_claims: bool,
+
+    /// `standard` or `chess960`, where openings may also be given as start position numbers
+    #[arg(long, default_value_t = game::Variant::Standard)]
+    variant: game::Variant,
+
+    /// Directory with Syzygy WDL tables to adjudicate positions they cover
+    #[arg(long, value_name = "DIR")]
+    tb: Option<String>,
+}
+
+impl GameArgs {
+    /// `--tc`, or TIME and INC in milliseconds
+    fn tc(&self, time: usize, inc: usize) -> tc::TimeControl {
+        self.tc.unwrap_or(tc::TimeControl::fischer(time, inc))
+    }
+
+    /// `tc` is the time control of engine A and B, or of white and black when watching
+    fn config(&self, tc: [tc::TimeControl; 2], variant: game::Variant) -> GameConfig {
+        if let Some(dir) = self.log_dir.as_ref() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                eprintln!("\x1b[1;31mError:\x1b[0m --log-dir: {dir}: {e}");
+                std::process::exit(1);
+            }
+        }
+
+        GameConfig {
+            tc,
+            timemargin: self.timemargin,
+            log_dir: self.log_dir.clone(),
+            handshake_timeout: std::time::Duration::from_millis(self.handshake_timeout),
+            move_timeout: std::time::Duration::from_millis(self.move_timeout),
+            fresh_engines: self.fresh_engines,
+            ponder: self.ponder,
+            draw_claims: !self.no_draw_claims,
+            variant,
+            adjudication: adjudicate::Rules {
+                resign: parse_or_exit(&self.resign, "--resign"),
+                draw: parse_or_exit(&self.draw, "--draw"),
+            },
+            tablebase: self.tb.as_ref().map(|dir| match adjudicate::Tablebase::open(dir) {
+                Ok(tb) => {
+                    println!("\x1b[1;32mInfo:\x1b[0m Loaded {}-piece Syzygy tables from {dir}", tb.max_pieces());
+                    tb
+                }
+                Err(e) => {
+                    eprintln!("\x1b[1;31mError:\x1b[0m --tb: {e}");
+                    std::process::exit(1);
+                }
+            }),
+        }
+    }
+}
+
+fn parse_or_exit<T: FromStr<Err = String>>(v: &[String], arg: &str) -> Option<T> {
+    if v.is_empty() {
+        return None;
+    }
+
+    match v.join(" ").parse() {
+        Ok(t) => Some(t),
+        Err(e) => {
+            eprintln!("\x1b[1;31mError:\x1b[0m {arg}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct OpeningArgs {
+    /// File with a FEN or EPD per line, a `.pgn` file of opening lines or a Polyglot `.bin` book
+    #[arg(long, alias = "openings", default_value = "openings.txt")]
     opening_positions: String,
 
+    /// How many moves of each game of a PGN opening file, or from a Polyglot book, to play
+    #[arg(long, default_value_t = usize::MAX)]
+    plies: usize,
+
+    /// Seed for picking moves from a Polyglot book, and for the perturbations when tuning
+    #[arg(long, default_value_t = 1, allow_negative_numbers = true)]
+    seed: i32,
+}
+
+#[derive(Debug, Args)]
+struct PlayArgs {
+    #[arg(required_unless_present = "resume")]
+    a: Option<String>,
+    #[arg(required_unless_present = "resume")]
+    b: Option<String>,
+
+    #[arg(required_unless_present_any = ["resume", "tc"], required_unless_present_all = ["a_tc", "b_tc"])]
+    time: Option<usize>,
+    #[arg(required_unless_present_any = ["resume", "tc"], required_unless_present_all = ["a_tc", "b_tc"])]
+    inc: Option<usize>,
+
+    /// Time control for engine A only, for time odds
+    #[arg(long)]
+    a_tc: Option<tc::TimeControl>,
+    /// Time control for engine B only, for time odds
+    #[arg(long)]
+    b_tc: Option<tc::TimeControl>,
+
+    #[command(flatten)]
+    openings: OpeningArgs,
+
     #[arg(long, action = ArgAction::SetTrue)]
     biased: bool,
 
@@ -45,6 +192,65 @@ struct PlayArgs {
     a_elo: f32,
     #[arg(long, default_value_t = 1200.0)]
     b_elo: f32,
+
+    /// Stop early once a sequential probability ratio test accepts either hypothesis
+    #[arg(long, num_args = 4, allow_negative_numbers = true, value_names = ["ELO0", "ELO1", "ALPHA", "BETA"])]
+    sprt: Option<Vec<f64>>,
+
+    /// UCI option to set for engine A, as `NAME=VALUE`
+    #[arg(long)]
+    a_option: Vec<engine::UciOption>,
+    /// UCI option to set for engine B, as `NAME=VALUE`
+    #[arg(long)]
+    b_option: Vec<engine::UciOption>,
+
+    /// Where to save the match state, defaults to `match_<timestamp>.state`
+    #[arg(long)]
+    state: Option<String>,
+
+    /// Continue the match saved in a state file, with its engines, time control, openings, ratings
+    /// and SPRT
+    #[arg(long, conflicts_with_all = [
+        "a", "b", "time", "inc", "tc", "a_tc", "b_tc", "a_option", "b_option", "state", "variant",
+        "opening_positions", "play_positions", "plies", "seed", "biased", "a_elo", "b_elo", "sprt",
+    ])]
+    resume: Option<String>,
+
+    #[command(flatten)]
+    game: GameArgs,
+}
+
+#[derive(Debug, Args)]
+struct TournamentArgs {
+    time: usize,
+    inc: usize,
+
+    #[arg(num_args = 2.., required = true)]
+    engines: Vec<String>,
+
+    /// Play the first engine against every other one instead of a round-robin
+    #[arg(long, action = ArgAction::SetTrue)]
+    gauntlet: bool,
+
+    #[command(flatten)]
+    openings: OpeningArgs,
+
+    #[arg(short = 'n', long, default_value_t = usize::MAX)]
+    play_positions: usize,
+
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Average rating of the field
+    #[arg(long, default_value_t = 1200.0)]
+    elo: f32,
+
+    /// UCI option to set for every engine, as `NAME=VALUE`
+    #[arg(long)]
+    option: Vec<engine::UciOption>,
+
+    #[command(flatten)]
+    game: GameArgs,
 }
 
 #[derive(Debug, Args)]
@@ -53,8 +259,8 @@ struct TuneArgs {
 
     initial_theta: String,
 
-    #[arg(long, default_value = "openings.txt")]
-    opening_positions: String,
+    #[command(flatten)]
+    openings: OpeningArgs,
 
     #[arg(short, long, default_value_t = 100)]
     iterations: usize,
@@ -62,10 +268,16 @@ struct TuneArgs {
     #[arg(short = 'n', long, default_value_t = 2)]
     play_positions: usize,
 
-    #[arg(long, default_value_t = 1)]
-    seed: i32,
     #[arg(short, long, default_value_t = 1)]
     jobs: usize,
+
+    /// UCI option to set for the engine, as `NAME=VALUE`
+    #[arg(long)]
+    option: Vec<engine::UciOption>,
+
+    /// `standard` or `chess960`, where openings may also be given as start position numbers
+    #[arg(long, default_value_t = game::Variant::Standard)]
+    variant: game::Variant,
 }
 
 #[derive(Debug, Args)]
@@ -78,91 +290,147 @@ struct WatchArgs {
 
     #[arg(default_value = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")]
     fen: String,
-}
 
-static THREADS: AtomicUsize = AtomicUsize::new(0);
+    /// UCI option to set for the white engine, as `NAME=VALUE`
+    #[arg(long)]
+    w_option: Vec<engine::UciOption>,
+    /// UCI option to set for the black engine, as `NAME=VALUE`
+    #[arg(long)]
+    b_option: Vec<engine::UciOption>,
+
+    #[command(flatten)]
+    game: GameArgs,
+}
 
 fn main() {
     let args = Args::parse();
 
     match args.command {
         Command::Play(play_args) => play(play_args),
+        Command::Tournament(tournament_args) => tournament(tournament_args),
         Command::Tune(tune_args) => tune(tune_args),
         Command::Watch(watch_args) => watch(watch_args),
     }
 }
 
 fn play(args: PlayArgs) {
-    let game_result = Arc::new([
-        AtomicUsize::new(0),
-        AtomicUsize::new(0),
-        AtomicUsize::new(0),
-    ]); // a win | draw | b win
-
-    let fens = get_fens(&args.opening_positions, args.play_positions);
-
-    let a_player = Arc::new(Player {
-        path: args.a.as_str().into(),
-        name: engine::Engine::get_name(args.a.as_str())
-            .map_or_else(|| args.a.as_str().into(), |a| a.as_str().into()),
+    let resumed = args.resume.as_ref().map(|path| {
+        let state = state::MatchState::load(path).unwrap_or_else(|e| {
+            eprintln!("\x1b[1;31mError:\x1b[0m {e}");
+            std::process::exit(1);
+        });
+
+        println!("\x1b[1;32mInfo:\x1b[0m resuming {path} with {} games played", state.games.len());
+        state
     });
-    let b_player = Arc::new(Player {
-        path: args.b.as_str().into(),
-        name: engine::Engine::get_name(args.b.as_str())
-            .map_or_else(|| args.b.as_str().into(), |a| a.as_str().into()),
+    let tc = |own: Option<tc::TimeControl>| {
+        own.or(args.game.tc)
+            .unwrap_or_else(|| tc::TimeControl::fischer(args.time.unwrap(), args.inc.unwrap()))
+    };
+    let info = resumed.as_ref().map_or_else(
+        || state::MatchInfo {
+            a: args.a.clone().unwrap(),
+            b: args.b.clone().unwrap(),
+            tc: [tc(args.a_tc), tc(args.b_tc)],
+            opening_positions: args.openings.opening_positions.clone(),
+            play_positions: args.play_positions,
+            plies: args.openings.plies,
+            seed: args.openings.seed,
+            biased: args.biased,
+            a_options: args.a_option.clone(),
+            b_options: args.b_option.clone(),
+            variant: args.game.variant,
+            elos: (args.a_elo, args.b_elo),
+            sprt: args.sprt.as_ref().map(|s| sprt::Sprt::new(s[0], s[1], s[2], s[3])),
+        },
+        |state| state.info.clone(),
+    );
+
+    let openings = openings::load(&info.opening_positions, info.play_positions, info.plies, info.seed, info.variant);
+
+    let config = Arc::new(args.game.config(info.tc, info.variant));
+    let a_player = Arc::new(Player::new(&info.a, &info.a_options, &config));
+    let b_player = Arc::new(Player::new(&info.b, &info.b_options, &config));
+
+    let mut state = resumed.unwrap_or_else(|| {
+        let path = args.state.clone().unwrap_or_else(|| {
+            format!("match_{}.state", std::time::UNIX_EPOCH.elapsed().unwrap().as_millis())
+        });
+
+        let state = state::MatchState::create(&path, info.clone()).unwrap_or_else(|e| {
+            eprintln!("\x1b[1;31mError:\x1b[0m {e}");
+            std::process::exit(1);
+        });
+
+        println!("\x1b[1;32mInfo:\x1b[0m match state is saved to {path}");
+        state
     });
-    let elos = Arc::new(Mutex::new((args.a_elo, args.b_elo)));
+
+    let mut stats = stats::Stats::new();
+    for &(pair, _, score, termination) in state.games.iter() {
+        stats.add(pair, score, termination);
+    }
+
+    let sprt = info.sprt;
+
+    let mut search = [stats::SearchStats::default(); 2];
+    let mut pool = pool::Pool::new(args.jobs);
 
     println!("\x1b[1;32mInfo:\x1b[0m initialization complete");
 
-    for fen in fens.iter() {
-        let game = chess::Game::from_str(fen).unwrap();
-        let fen = fen.as_str().into();
-
-        play_single(
-            Arc::clone(&a_player),
-            Arc::clone(&b_player),
-            Some(Arc::clone(&elos)),
-            game.clone(),
-            Arc::clone(&fen),
-            args.time,
-            args.inc,
-            Arc::clone(&game_result),
-            false,
-            args.jobs,
-        );
+    'openings: for (i, opening) in openings.iter().enumerate() {
+        let game = opening.game(info.variant);
+        let opening = Arc::new(opening.clone());
+
+        for polarity in [false, true] {
+            if polarity && info.biased {
+                break;
+            }
+
+            while pool.is_full() {
+                if let Some(result) = pool.recv() {
+                    record(&mut stats, &mut state, sprt.as_ref(), &mut search, result);
+                }
+            }
+
+            // only ever stop between openings so that game pairs stay complete
+            if let Some(sprt) = sprt.as_ref() {
+                if !polarity && sprt.status(sprt.llr(&stats.distribution())).is_some() {
+                    break 'openings;
+                }
+            }
 
-        if !args.biased {
-            play_single(
-                Arc::clone(&b_player),
-                Arc::clone(&a_player),
-                Some(Arc::clone(&elos)),
-                game.clone(),
-                Arc::clone(&fen),
-                args.time,
-                args.inc,
-                Arc::clone(&game_result),
-                true,
-                args.jobs,
-            );
+            if state.is_done(i, polarity) {
+                continue;
+            }
+
+            let (a, b, elos) = if !polarity {
+                (Arc::clone(&a_player), Arc::clone(&b_player), info.elos)
+            } else {
+                (Arc::clone(&b_player), Arc::clone(&a_player), (info.elos.1, info.elos.0))
+            };
+            let game = game.clone();
+            let opening = Arc::clone(&opening);
+            let config = Arc::clone(&config);
+
+            pool.submit(move || {
+                (i, polarity, play_single(a, b, Some(elos), game, opening, config, polarity))
+            });
         }
     }
 
-    while THREADS.load(Ordering::Relaxed) != 0 {
-        core::hint::spin_loop();
+    while let Some(result) = pool.recv() {
+        record(&mut stats, &mut state, sprt.as_ref(), &mut search, result);
     }
 
-    let total = fens.len() * (2 - args.biased as usize);
-
-    let a = game_result[0].load(Ordering::Relaxed);
-    let d = game_result[1].load(Ordering::Relaxed);
-    let b = game_result[2].load(Ordering::Relaxed);
+    let [a, d, b] = stats.wdl;
+    let total = stats.games();
 
     let term_size = term_size::dimensions().unwrap_or((80, 24));
     let bar_length = term_size.0 - 4;
 
-    let a_bar_length = ((a as f32 / total as f32) * bar_length as f32).round() as usize;
-    let d_bar_length = ((d as f32 / total as f32) * bar_length as f32).round() as usize;
+    let a_bar_length = ((a as f32 / total.max(1) as f32) * bar_length as f32).round() as usize;
+    let d_bar_length = ((d as f32 / total.max(1) as f32) * bar_length as f32).round() as usize;
     let b_bar_length = bar_length - a_bar_length - d_bar_length;
 
     let a_bar = "━".repeat(a_bar_length);
@@ -182,104 +450,158 @@ fn play(args: PlayArgs) {
     let l_indent = "=".repeat(l_indent_length);
     let r_indent = "=".repeat(r_indent_length);
 
-    let elos = elos.lock().unwrap();
+    let ratings = elo::estimate(2, &[(0, 1, stats.wdl)], (info.elos.0 as f64 + info.elos.1 as f64) / 2.0);
 
     println!("\n\n\x1b[1m{} SUMMARY {}\x1b[0m", l_indent, r_indent);
     println!("\x1b[1mTotal:\x1b[0m {total} games");
     println!("  \x1b[32m{a}\x1b[90m\x1b[{d_pad}C{d}\x1b[31m\x1b[{b_pad}C{b}\x1b[0m");
     println!("  \x1b[32m{a_bar}\x1b[90m{d_bar}\x1b[31m{b_bar}\x1b[0m");
-    println!("\n \x1b[1mElo:\x1b[0m A: {:.0}, B: {:.0}", elos.0, elos.1);
+    println!(
+        "\n \x1b[1mElo:\x1b[0m A: {:.0}, B: {:.0} \x1b[90m(draw elo {:.0})\x1b[0m",
+        ratings.elo[0], ratings.elo[1], ratings.draw_elo,
+    );
+    println!(" \x1b[1mSearch:\x1b[0m A: {}; B: {}", search[0], search[1]);
+
+    if let Some(e) = stats.estimate() {
+        println!(
+            " \x1b[1mElo difference:\x1b[0m {:+.1} ± {:.1} (95%), \x1b[1mLOS:\x1b[0m {:.1}%, \x1b[1mDraw ratio:\x1b[0m {:.1}%",
+            e.elo,
+            e.error,
+            e.los * 100.0,
+            stats.draw_ratio() * 100.0,
+        );
+    }
+
+    if stats.pairs() != 0 {
+        let [ll, ld, dd, wd, ww] = stats.penta;
+        println!(" \x1b[1mPentanomial:\x1b[0m LL: {ll}, LD: {ld}, DD/WL: {dd}, WD: {wd}, WW: {ww}");
+    }
+
+    println!(" \x1b[1mTerminations:\x1b[0m {}", stats.terminations());
+    println!(
+        " \x1b[1mForfeits:\x1b[0m {}: {}; {}: {}",
+        a_player.name, stats.forfeits(0), b_player.name, stats.forfeits(1),
+    );
+
+    // games of openings sharing an id are counted together, openings without one aren't listed
+    let mut by_opening: Vec<(&str, stats::Stats)> = Vec::new();
+    for &(i, _, score, termination) in state.games.iter() {
+        let Some(id) = openings.get(i).and_then(|o| o.id.as_deref()) else {
+            continue;
+        };
+
+        match by_opening.iter_mut().find(|(o, _)| *o == id) {
+            Some((_, s)) => s.add(i, score, termination),
+            None => {
+                let mut s = stats::Stats::new();
+                s.add(i, score, termination);
+                by_opening.push((id, s));
+            }
+        }
+    }
+
+    if !by_opening.is_empty() {
+        println!(" \x1b[1mOpenings:\x1b[0m");
+    }
+
+    for (id, s) in by_opening.iter() {
+        let [w, d, l] = s.wdl;
+        let score = (w as f64 + d as f64 / 2.0) / s.games() as f64;
+
+        println!("   {id}: {}: +{w} ={d} -{l}, {:.1}%", a_player.name, score * 100.0);
+    }
+
+    if let Some(sprt) = sprt {
+        let llr = sprt.llr(&stats.distribution());
+        let verdict = match sprt.status(llr) {
+            Some(sprt::Hypothesis::H0) => "\x1b[31mH0 accepted\x1b[0m",
+            Some(sprt::Hypothesis::H1) => "\x1b[32mH1 accepted\x1b[0m",
+            None => "\x1b[90minconclusive\x1b[0m",
+        };
+
+        println!(" \x1b[1mSPRT:\x1b[0m {}, {verdict}", sprt.report(llr));
+    }
+}
+
+fn tournament(args: TournamentArgs) {
+    let openings = openings::load(&args.openings.opening_positions, args.play_positions, args.openings.plies, args.openings.seed, args.game.variant);
+
+    tournament::tournament(
+        &args.engines,
+        &args.option,
+        &openings,
+        args.game.config([args.game.tc(args.time, args.inc); 2], args.game.variant),
+        args.gauntlet,
+        args.jobs,
+        args.elo as f64,
+    );
 }
 
 fn tune(args: TuneArgs) {
     let theta = tune::FeatureVector::<f32>::from_binary(&std::fs::read(args.initial_theta).unwrap());
-    let fens = get_fens(&args.opening_positions, args.play_positions);
+    let openings = openings::load(&args.openings.opening_positions, args.play_positions, args.openings.plies, args.openings.seed, args.variant);
+
+    let config = tune::config(args.variant);
+    let player = Arc::new(Player::new(&args.engine, &args.option, &config));
 
-    tune::tune(args.iterations, &args.engine, theta, &fens, args.seed, args.jobs);
+    tune::tune(args.iterations, &player, theta, &openings, args.openings.seed, args.jobs, config);
 }
 
 fn watch(args: WatchArgs) {
-    let w_name = engine::Engine::get_name(args.w.as_str()).unwrap_or_else(|| args.w.clone());
-    let b_name = engine::Engine::get_name(args.b.as_str()).unwrap_or_else(|| args.b.clone());
+    let config = args.game.config([args.game.tc(args.time, args.inc); 2], args.game.variant);
+    let opening = openings::Opening::parse(&args.fen, config.variant).unwrap_or_else(|e| {
+        eprintln!("\x1b[1;31mError:\x1b[0m {e}");
+        std::process::exit(1);
+    });
+    let fen = opening.fen.as_str();
+    let mut game = opening.game(config.variant);
 
-    let mut w_engine = engine::Engine::new(&args.w, &args.fen);
-    let mut b_engine = engine::Engine::new(&args.b, &args.fen);
+    let w_player = Player::new(&args.w, &args.w_option, &config);
+    let b_player = Player::new(&args.b, &args.b_option, &config);
+    let w_name = Arc::clone(&w_player.name);
+    let b_name = Arc::clone(&b_player.name);
 
-    let mut game = chess::Game::from_str(&args.fen).unwrap();
-    let mut game_result = [0; 3];
+    let id = pgn::game_id();
+    let mut engines = [
+        w_player.start(fen, config.log(&id, chess::Color::White).as_deref(), config.handshake_timeout),
+        b_player.start(fen, config.log(&id, chess::Color::Black).as_deref(), config.handshake_timeout),
+    ];
 
-    let mut tc = (args.time, args.time); // w | b
-    let mut overtime = 0;
+    let mut search = [stats::SearchStats::default(); 2]; // w | b
+    let mut moves = Vec::new();
 
     render::render(&game.current_position(), &w_name, &b_name, None);
 
-    'a: while game.result().is_none() {
-        if let Some(m) = w_engine.get_move(&mut game, &mut tc, args.inc) {
-            render::render(&game.current_position(), &w_name, &b_name, Some(m));
-        } else {
-            overtime = 1;
-            break 'a;
-        }
-
+    let [w_engine, b_engine] = &mut engines;
+    let (termination, detail) = referee([w_engine, b_engine], &mut game, &config, config.tc, |side, game, m| {
+        search[side].add(&m.info);
+        render::render(&game.current_position(), &w_name, &b_name, Some(m.mv));
+        moves.push(m);
+    });
 
-        if game.can_declare_draw() {
-            game.declare_draw();
-        }
+    let result = pgn::result(&game);
+    let filename = pgn::export_pgn(&id, &game, &w_name, &b_name, &opening, None, &moves, config.tc, termination, detail.as_deref());
+    let termination = detail.unwrap_or_else(|| termination.to_string());
 
-        if game.result().is_some() {
-            break 'a;
-        }
-
-        if let Some(m) = b_engine.get_move(&mut game, &mut tc, args.inc) {
-            render::render(&game.current_position(), &w_name, &b_name, Some(m));
-        } else {
-            overtime = 2;
-            break 'a;
-        }
+    println!("\x1b[10B\x1b[1;32mInfo:\x1b[0m {w_name} vs {b_name} ({result}, {termination}) was exported to {filename}");
+    println!("\x1b[1;32mInfo:\x1b[0m {w_name}: {}; {b_name}: {}", search[0], search[1]);
+}
 
-        if game.can_declare_draw() {
-            game.declare_draw();
-        }
-    }
+fn record(
+    stats: &mut stats::Stats,
+    state: &mut state::MatchState,
+    sprt: Option<&sprt::Sprt>,
+    search: &mut [stats::SearchStats; 2],
+    (pair, polarity, report): (usize, bool, GameReport),
+) {
+    stats.add(pair, report.score, report.termination);
+    state.add(pair, polarity, report.score, report.termination);
+    search[0].merge(&report.search[0]);
+    search[1].merge(&report.search[1]);
 
-    if overtime == 1 {
-        game_result[2] += 1;
-    } else if overtime == 2 {
-        game_result[0] += 1;
-    } else {
-        match game.result() {
-            Some(chess::GameResult::WhiteCheckmates) => {
-                game_result[0] += 1;
-            }
-            Some(chess::GameResult::BlackCheckmates) => {
-                game_result[2] += 1;
-            }
-            Some(
-                chess::GameResult::DrawAccepted
-                | chess::GameResult::DrawDeclared
-                | chess::GameResult::Stalemate,
-                ) => {
-                game_result[1] += 1;
-            }
-            _ => unreachable!(),
-        }
+    if let Some(sprt) = sprt {
+        println!("\x1b[1;32mInfo:\x1b[0m {}", sprt.report(sprt.llr(&stats.distribution())));
     }
-
-    let filename = pgn::export_pgn(&game, &w_name, &b_name, &args.fen, None);
-
-    println!("\x1b[10B\x1b[1;32mInfo:\x1b[0m {w_name} vs {b_name} was exported to {filename}");
-}
-
-fn get_fens(file: &str, n: usize) -> Vec<String> {
-    std::fs::read_to_string(file)
-        .unwrap()
-        .trim()
-        .lines()
-        .take(n)
-        .map(|a| a.to_string())
-        .collect()
-        // .map(|fen| (chess::Game::from_str(fen).unwrap(), fen.to_string()))
-        // .collect::<Vec<(chess::Game, String)>>();
 }
 
 fn flip(idx: usize, flip: bool, n: usize) -> usize {
@@ -290,129 +612,439 @@ fn flip(idx: usize, flip: bool, n: usize) -> usize {
     }
 }
 
+thread_local! {
+    /// Engine processes kept between games by each worker, by [`Player::key`]
+    static ENGINES: std::cell::RefCell<std::collections::HashMap<String, Vec<engine::Engine>>> =
+        Default::default();
+}
+
 pub struct Player {
     pub path: Arc<str>,
     pub name: Arc<str>,
+    pub options: Arc<[engine::UciOption]>,
+}
+
+impl Player {
+    /// Asks the engine for its name and makes sure that it supports every given option,
+    /// exiting with an error otherwise. Engines with a `Ponder` option get it turned on when
+    /// pondering, unless it is set explicitly, and `UCI_Chess960` is set for Chess960.
+    pub fn new(path: &str, options: &[engine::UciOption], config: &GameConfig) -> Self {
+        let chess960 = config.variant == game::Variant::Chess960;
+        let info = engine::Engine::get_info(path, config.handshake_timeout)
+            .and_then(|info| info.check_options(options).map(|_| info).map_err(|e| format!("{path}: {e}")))
+            .and_then(|info| {
+                if chess960 && !info.options.iter().any(|o| o.eq_ignore_ascii_case("UCI_Chess960")) {
+                    return Err(format!("{path}: the engine doesn't support Chess960"));
+                }
+
+                Ok(info)
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("\x1b[1;31mError:\x1b[0m {e}");
+                std::process::exit(1);
+            });
+
+        let mut options = options.to_vec();
+        let is_ponder = |name: &str| name.eq_ignore_ascii_case("Ponder");
+
+        if config.ponder && info.options.iter().any(|o| is_ponder(o)) && !options.iter().any(|o| is_ponder(&o.name)) {
+            options.push("Ponder=true".parse().unwrap());
+        }
+
+        if chess960 {
+            options.retain(|o| !o.name.eq_ignore_ascii_case("UCI_Chess960"));
+            options.push("UCI_Chess960=true".parse().unwrap());
+        }
+
+        Self {
+            path: path.into(),
+            name: info.name.as_deref().unwrap_or(path).into(),
+            options: options.into(),
+        }
+    }
+
+    /// Starts the engine for a game. An engine that passed the handshake before but fails it
+    /// now ends the match, since every game it plays would be lost the same way.
+    pub fn start(&self, fen: &str, log: Option<&str>, timeout: std::time::Duration) -> engine::Engine {
+        engine::Engine::new(&self.path, fen, &self.options, log, timeout).unwrap_or_else(|e| {
+            eprintln!("\x1b[1;31mError:\x1b[0m {e}");
+            std::process::exit(1);
+        })
+    }
+
+    /// Takes one of this worker's engines left over from earlier games and readies it for a new
+    /// one, starting a new process if there is none or it has died
+    pub fn checkout(&self, fen: &str, log: Option<&str>, timeout: std::time::Duration) -> engine::Engine {
+        let engine = ENGINES.with_borrow_mut(|e| e.get_mut(&self.key()).and_then(Vec::pop));
+
+        if let Some(mut engine) = engine {
+            if engine.new_game(fen, log, timeout).is_ok() {
+                return engine;
+            }
+        }
+
+        self.start(fen, log, timeout)
+    }
+
+    /// Keeps `engine` around for the next game on this worker
+    pub fn checkin(&self, engine: engine::Engine) {
+        ENGINES.with_borrow_mut(|e| e.entry(self.key()).or_default().push(engine));
+    }
+
+    // engines are only interchangeable if they were set up the same way
+    fn key(&self) -> String {
+        self.options.iter().fold(self.path.to_string(), |k, o| format!("{k} {o}"))
+    }
 }
 
 fn play_single(
     a: Arc<Player>,
     b: Arc<Player>,
-    elos: Option<Arc<Mutex<(f32, f32)>>>,
-    game: chess::Game,
-    fen: Arc<str>,
-    time: usize,
-    inc: usize,
-    game_result: Arc<[AtomicUsize; 3]>,
+    elos: Option<(f32, f32)>,
+    game: game::Game,
+    opening: Arc<openings::Opening>,
+    config: Arc<GameConfig>,
     polarity: bool,
-    jobs: usize,
-) {
-    let a_engine = engine::Engine::new(a.path.as_ref(), &fen);
-    let b_engine = engine::Engine::new(b.path.as_ref(), &fen);
+) -> GameReport {
+    let id = pgn::game_id();
+    let a_log = config.log(&id, chess::Color::White);
+    let b_log = config.log(&id, chess::Color::Black);
+
+    if config.fresh_engines {
+        let mut a_engine = a.start(&opening.fen, a_log.as_deref(), config.handshake_timeout);
+        let mut b_engine = b.start(&opening.fen, b_log.as_deref(), config.handshake_timeout);
 
-    play_with_engine(a_engine, b_engine, Arc::clone(&a.name), Arc::clone(&b.name), elos, game, fen, time, inc, game_result, polarity, jobs)
+        return play_with_engine(&id, &mut a_engine, &mut b_engine, Arc::clone(&a.name), Arc::clone(&b.name), elos, game, opening, &config, polarity);
+    }
+
+    let mut a_engine = a.checkout(&opening.fen, a_log.as_deref(), config.handshake_timeout);
+    let mut b_engine = b.checkout(&opening.fen, b_log.as_deref(), config.handshake_timeout);
+
+    let report = play_with_engine(&id, &mut a_engine, &mut b_engine, Arc::clone(&a.name), Arc::clone(&b.name), elos, game, opening, &config, polarity);
+
+    a.checkin(a_engine);
+    b.checkin(b_engine);
+
+    report
 }
 
-fn play_with_engine(
-    mut a_engine: engine::Engine,
-    mut b_engine: engine::Engine,
+/// Settings shared by every game of a match
+pub struct GameConfig {
+    pub tc: [tc::TimeControl; 2], // a | b
+    pub timemargin: usize,
+    pub log_dir: Option<String>,
+    pub handshake_timeout: std::time::Duration,
+    pub move_timeout: std::time::Duration,
+    pub fresh_engines: bool,
+    pub ponder: bool,
+    pub draw_claims: bool, // whether draws by threefold repetition and the fifty-move rule are claimed
+    pub variant: game::Variant,
+    pub adjudication: adjudicate::Rules,
+    pub tablebase: Option<adjudicate::Tablebase>,
+}
 
-    a_name: Arc<str>,
-    b_name: Arc<str>,
+impl GameConfig {
+    /// Where to log the engine playing `color` in game `id`, if logging at all
+    fn log(&self, id: &str, color: chess::Color) -> Option<String> {
+        let color = if color == chess::Color::White { "white" } else { "black" };
 
-    elos: Option<Arc<Mutex<(f32, f32)>>>,
+        self.log_dir.as_ref().map(|dir| format!("{dir}/game_{id}_{color}.log"))
+    }
+}
 
-    mut game: chess::Game,
-    fen: Arc<str>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Termination {
+    Normal,
+    TimeForfeit,
+    IllegalMove,
+    Disconnected,
+    ProtocolError,
+    ResignAdjudication,
+    DrawAdjudication,
+    TablebaseAdjudication,
+    InsufficientMaterial,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    FivefoldRepetition,
+    SeventyFiveMoveRule,
+}
 
-    time: usize,
-    inc: usize,
+impl Termination {
+    pub const ALL: [Self; 13] = [
+        Self::Normal,
+        Self::TimeForfeit,
+        Self::IllegalMove,
+        Self::Disconnected,
+        Self::ProtocolError,
+        Self::ResignAdjudication,
+        Self::DrawAdjudication,
+        Self::TablebaseAdjudication,
+        Self::InsufficientMaterial,
+        Self::ThreefoldRepetition,
+        Self::FiftyMoveRule,
+        Self::FivefoldRepetition,
+        Self::SeventyFiveMoveRule,
+    ];
+
+    /// Value of the PGN `Termination` tag
+    pub fn pgn_tag(self) -> &'static str {
+        match self {
+            Self::Normal
+            | Self::InsufficientMaterial
+            | Self::ThreefoldRepetition
+            | Self::FiftyMoveRule
+            | Self::FivefoldRepetition
+            | Self::SeventyFiveMoveRule => "normal",
+            Self::TimeForfeit => "time forfeit",
+            Self::IllegalMove => "illegal move",
+            Self::Disconnected => "abandoned",
+            Self::ProtocolError => "rules infraction",
+            Self::ResignAdjudication | Self::DrawAdjudication | Self::TablebaseAdjudication => {
+                "adjudication"
+            }
+        }
+    }
 
-    game_result: Arc<[AtomicUsize; 3]>,
-    polarity: bool,
-    jobs: usize,
-) {
-    while THREADS.load(Ordering::Relaxed) >= jobs {
-        core::hint::spin_loop();
+    /// Whether the losing side failed to make a proper move
+    pub fn is_forfeit(self) -> bool {
+        matches!(
+            self,
+            Self::TimeForfeit | Self::IllegalMove | Self::Disconnected | Self::ProtocolError
+        )
     }
 
-    THREADS.fetch_add(1, Ordering::Relaxed);
-    std::thread::spawn(move || {
-        let (w_name, b_name) = if !polarity { (Arc::clone(&a_name), Arc::clone(&b_name)) } else { (Arc::clone(&b_name), Arc::clone(&a_name)) };
+    /// Name used in match state files
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::TimeForfeit => "time-forfeit",
+            Self::IllegalMove => "illegal-move",
+            Self::Disconnected => "disconnected",
+            Self::ProtocolError => "protocol-error",
+            Self::ResignAdjudication => "resign-adjudication",
+            Self::DrawAdjudication => "draw-adjudication",
+            Self::TablebaseAdjudication => "tablebase-adjudication",
+            Self::InsufficientMaterial => "insufficient-material",
+            Self::ThreefoldRepetition => "threefold-repetition",
+            Self::FiftyMoveRule => "fifty-move-rule",
+            Self::FivefoldRepetition => "fivefold-repetition",
+            Self::SeventyFiveMoveRule => "seventy-five-move-rule",
+        }
+    }
+}
 
-        let mut tc = (time, time); // w | b
-        let mut overtime = 0;
-        let mut r = [0.0; 2];
+impl FromStr for Termination {
+    type Err = ();
 
-        'a: while game.result().is_none() {
-            if a_engine.get_move(&mut game, &mut tc, inc).is_none() {
-                overtime = 1;
-                break 'a;
-            }
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::ALL.into_iter().find(|t| t.name() == s).ok_or(())
+    }
+}
 
-            if game.can_declare_draw() {
-                game.declare_draw();
-            }
+impl std::fmt::Display for Termination {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.name().replace('-', " "))
+    }
+}
 
-            if game.result().is_some() {
-                break 'a;
-            }
+pub struct GameReport {
+    pub score: f64,                       // score of a
+    pub search: [stats::SearchStats; 2], // a | b
+    pub termination: Termination,
+}
 
-            if b_engine.get_move(&mut game, &mut tc, inc).is_none() {
-                overtime = 2;
-                break 'a;
+/// Lets the white and black engine take turns until the game is over, calling `on_move` with
+/// the index of the engine after each move. A side that forfeits is made to resign, and what
+/// happened is returned along with the termination.
+fn referee(
+    engines: [&mut engine::Engine; 2],
+    game: &mut game::Game,
+    config: &GameConfig,
+    tc: [tc::TimeControl; 2], // w | b
+    mut on_move: impl FnMut(usize, &game::Game, engine::MoveRecord),
+) -> (Termination, Option<String>) {
+    let mut clocks = tc.map(tc::Clock::new);
+    let mut adjudicator = adjudicate::Adjudicator::new(config.adjudication);
+    // the game may already have book moves played
+    let fen = game.fen();
+    let mut movenumber = fen
+        .split_whitespace()
+        .nth(5)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1);
+    let mut halfmoves = fen
+        .split_whitespace()
+        .nth(4)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    // occurrences of each position since the last irreversible move
+    let mut seen = std::collections::HashMap::from([(game.hash(), 1)]);
+    let mut turn = game.side_to_move().to_index();
+
+    while game.result().is_none() {
+        let color = game.side_to_move();
+        let before = game.current_position();
+
+        let m = match engines[turn].get_move(game, &mut clocks, config.timemargin, config.move_timeout, config.ponder) {
+            Ok(m) => m,
+            Err(forfeit) => {
+                game.resign(color);
+
+                let termination = match forfeit {
+                    engine::EngineError::IllegalMove { .. } => Termination::IllegalMove,
+                    engine::EngineError::Disconnected => Termination::Disconnected,
+                    engine::EngineError::TimeForfeit(_) => Termination::TimeForfeit,
+                    engine::EngineError::ProtocolError(_) => Termination::ProtocolError,
+                };
+
+                return (termination, Some(format!("{color:?} {forfeit}")));
             }
+        };
+
+        let mv = m.mv;
+        let verdict = adjudicator.update(color, m.info.score, movenumber);
+        on_move(turn, game, m);
+
+        // castling in Chess960 is the king taking its own rook
+        if before.piece_on(mv.get_source()) == Some(chess::Piece::Pawn)
+            || before.color_on(mv.get_dest()) == Some(!color)
+        {
+            halfmoves = 0;
+            seen.clear();
+        } else {
+            halfmoves += 1;
+        }
 
-            if game.can_declare_draw() {
-                game.declare_draw();
+        let board = game.current_position();
+        let repetitions = seen.entry(game.hash()).or_insert(0);
+        *repetitions += 1;
+
+        if game.result().is_none() {
+            // rule based draws, the automatic ones taking precedence over the claimable ones
+            let draw = if insufficient_material(&board) {
+                Some(Termination::InsufficientMaterial)
+            } else if *repetitions >= 5 {
+                Some(Termination::FivefoldRepetition)
+            } else if halfmoves >= 150 {
+                Some(Termination::SeventyFiveMoveRule)
+            } else if config.draw_claims && *repetitions >= 3 {
+                Some(Termination::ThreefoldRepetition)
+            } else if config.draw_claims && halfmoves >= 100 {
+                Some(Termination::FiftyMoveRule)
+            } else {
+                None
+            };
+
+            // the PGN `Termination` tag is just `normal` for these
+            if let Some(draw) = draw {
+                game.draw();
+                return (draw, Some(format!("Draw by {draw}")));
             }
-        }
 
-        if overtime == 1 {
-            game_result[flip(2, polarity, 2)].fetch_add(1, Ordering::Relaxed);
-            r[flip(1, polarity, 1)] = 1.0;
-        } else if overtime == 2 {
-            game_result[flip(0, polarity, 2)].fetch_add(1, Ordering::Relaxed);
-            r[flip(0, polarity, 1)] = 1.0;
-        } else {
-            match game.result() {
-                Some(chess::GameResult::WhiteCheckmates) => {
-                    game_result[flip(0, polarity, 2)].fetch_add(1, Ordering::Relaxed);
-                    r[flip(0, polarity, 1)] = 1.0;
-                }
-                Some(chess::GameResult::BlackCheckmates) => {
-                    game_result[flip(2, polarity, 2)].fetch_add(1, Ordering::Relaxed);
-                    r[flip(1, polarity, 1)] = 1.0;
-                }
-                Some(
-                    chess::GameResult::DrawAccepted
-                    | chess::GameResult::DrawDeclared
-                    | chess::GameResult::Stalemate,
-                ) => {
-                    game_result[1].fetch_add(1, Ordering::Relaxed);
-                    r = [0.5; 2];
+            let tb = config.tablebase.as_ref().and_then(|tb| tb.probe(&game.current_position(), halfmoves));
+
+            if let Some(v) = tb.or(verdict) {
+                match v {
+                    adjudicate::Verdict::Resign(loser) => game.resign(loser),
+                    adjudicate::Verdict::Draw => game.draw(),
                 }
-                _ => unreachable!(),
+
+                let termination = match (tb, v) {
+                    (Some(_), _) => Termination::TablebaseAdjudication,
+                    (None, adjudicate::Verdict::Resign(_)) => Termination::ResignAdjudication,
+                    (None, adjudicate::Verdict::Draw) => Termination::DrawAdjudication,
+                };
+
+                return (termination, None);
             }
         }
 
-        if let Some(elos) = elos {
-            let (mut w_pe, mut b_pe, mut w_e, mut b_e) = elo::update(&elos, r[0], r[1]);
+        if color == chess::Color::Black {
+            movenumber += 1;
+        }
 
-            if polarity {
-                core::mem::swap(&mut w_pe, &mut b_pe);
-                core::mem::swap(&mut w_e, &mut b_e);
-            }
+        turn ^= 1;
+    }
 
-            let filename = pgn::export_pgn(&game, &w_name, &b_name, &fen, Some((w_e, b_e)));
+    (Termination::Normal, None)
+}
 
-            println!("\x1b[1;32mInfo:\x1b[0m {w_name} \x1b[90m({w_pe:.0}→{w_e:.0})\x1b[0m vs {b_name} \x1b[90m({b_pe:.0}→{b_e:.0})\x1b[0m was exported to {filename}");
-        } else {
-            let filename = pgn::export_pgn(&game, &w_name, &b_name, &fen, None);
+/// Whether neither side can possibly checkmate: lone kings with at most one minor piece, or
+/// bishops that are all on the same color
+fn insufficient_material(board: &chess::Board) -> bool {
+    use chess::Piece::*;
 
-            println!("\x1b[1;32mInfo:\x1b[0m {w_name} vs {b_name} was exported to {filename}");
-        }
+    if [Pawn, Rook, Queen].iter().any(|&p| board.pieces(p).popcnt() != 0) {
+        return false;
+    }
+
+    let knights = *board.pieces(Knight);
+    let bishops = *board.pieces(Bishop);
+    let dark = chess::BitBoard::new(0xaa55_aa55_aa55_aa55);
+
+    (knights | bishops).popcnt() <= 1
+        || (knights.popcnt() == 0 && ((bishops & dark).popcnt() == 0 || (bishops & !dark).popcnt() == 0))
+}
+
+/// Plays out a game with `w_engine` as white. The score, search statistics and time controls
+/// are for the engines passed in as `a` and `b`, which are swapped when `polarity` is true.
+#[allow(clippy::too_many_arguments)]
+fn play_with_engine(
+    id: &str,
+
+    w_engine: &mut engine::Engine,
+    b_engine: &mut engine::Engine,
+
+    w_name: Arc<str>,
+    b_name: Arc<str>,
+
+    elos: Option<(f32, f32)>,
+
+    mut game: game::Game,
+    opening: Arc<openings::Opening>,
+
+    config: &GameConfig,
 
-        THREADS.fetch_sub(1, Ordering::Relaxed);
+    polarity: bool,
+) -> GameReport {
+    let mut r = [0.0; 2];
+    let mut search = [stats::SearchStats::default(); 2];
+    let mut moves = Vec::new();
+
+    let tc = [config.tc[flip(0, polarity, 1)], config.tc[flip(1, polarity, 1)]];
+
+    let (termination, detail) = referee([w_engine, b_engine], &mut game, config, tc, |side, _, m| {
+        search[flip(side, polarity, 1)].add(&m.info);
+        moves.push(m);
     });
+
+    match game.result() {
+        Some(chess::GameResult::WhiteCheckmates | chess::GameResult::BlackResigns) => {
+            r[flip(0, polarity, 1)] = 1.0;
+        }
+        Some(chess::GameResult::BlackCheckmates | chess::GameResult::WhiteResigns) => {
+            r[flip(1, polarity, 1)] = 1.0;
+        }
+        Some(
+            chess::GameResult::DrawAccepted
+            | chess::GameResult::DrawDeclared
+            | chess::GameResult::Stalemate,
+        ) => {
+            r = [0.5; 2];
+        }
+        None => unreachable!(),
+    }
+
+    let filename = pgn::export_pgn(id, &game, &w_name, &b_name, &opening, elos, &moves, tc, termination, detail.as_deref());
+
+    println!(
+        "\x1b[1;32mInfo:\x1b[0m {w_name} vs {b_name} ({}) was exported to {filename}",
+        detail.as_deref().unwrap_or(&termination.to_string()),
+    );
+
+    GameReport {
+        score: r[0],
+        search,
+        termination,
+    }
 }
@@ -0,0 +1,316 @@
+use crate::game::{Game, Variant};
+
+/// A start position from an openings file, with the book moves played from it if it came from
+/// a PGN or a Polyglot book
+#[derive(Debug, Clone)]
+pub struct Opening {
+    pub fen: String,
+    /// From the EPD `id` opcode or the PGN `Opening` tag
+    pub id: Option<String>,
+    pub moves: Vec<chess::ChessMove>,
+}
+
+// a game of a PGN file, with its moves in SAN
+#[derive(Default)]
+struct PgnGame {
+    tags: Vec<(String, String)>,
+    moves: Vec<String>,
+}
+
+const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+impl Opening {
+    /// Parses a line with a FEN, or an EPD with the `hmvc`, `fmvn` and `id` opcodes taken into
+    /// account and any other ones ignored. A FEN may have operations after its move counters too.
+    pub fn parse(line: &str, variant: Variant) -> Result<Self, String> {
+        let line = variant.opening(line);
+
+        let mut fields = Vec::new();
+        let mut rest = line.trim();
+
+        while fields.len() < 4 && !rest.is_empty() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            fields.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+
+        if fields.len() < 4 {
+            return Err(format!("expected a FEN or EPD, got `{line}`"));
+        }
+
+        let mut halfmoves = "0".to_string();
+        let mut fullmoves = "1".to_string();
+        let mut id = None;
+
+        // FEN move counters, which may be followed by EPD operations all the same
+        for counter in [&mut halfmoves, &mut fullmoves] {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if rest[..end].parse::<u32>().is_err() {
+                break;
+            }
+
+            *counter = rest[..end].to_string();
+            rest = rest[end..].trim_start();
+        }
+
+        for (opcode, operand) in operations(rest) {
+            match opcode {
+                "hmvc" => halfmoves = operand,
+                "fmvn" => fullmoves = operand,
+                "id" => id = Some(operand),
+                _ => {}
+            }
+        }
+
+        let fen = format!("{} {halfmoves} {fullmoves}", fields.join(" "));
+        Game::new(&fen, variant)?;
+
+        Ok(Self {
+            fen,
+            id,
+            moves: Vec::new(),
+        })
+    }
+
+    /// The first `plies` moves of `pgn` from its `FEN` tag or the standard start position
+    fn from_pgn(pgn: &PgnGame, plies: usize, variant: Variant) -> Result<Self, String> {
+        let tag = |key: &str| pgn.tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+
+        let fen = tag("FEN").unwrap_or_else(|| STARTPOS.to_string());
+        let mut game = Game::new(&fen, variant)?;
+
+        for san in pgn.moves.iter().take(plies) {
+            let m = crate::pgn::parse_san(&game, san)
+                .ok_or_else(|| format!("illegal move `{san}` in {}", game.fen()))?;
+            game.make_move(m);
+        }
+
+        Ok(Self {
+            fen,
+            id: tag("Opening"),
+            moves: game.moves().to_vec(),
+        })
+    }
+
+    /// Up to `plies` moves from the standard start position picked from `book`, the same ones for
+    /// the same `seed`. The line ends early once the book runs out or a position comes up again,
+    /// as books made from games have lines that go round in circles.
+    fn from_book(book: &crate::book::Book, plies: usize, mut seed: i32, variant: Variant) -> Result<Self, String> {
+        let mut game = Game::new(STARTPOS, variant)?;
+        let mut seen = std::collections::HashSet::from([game.hash()]);
+
+        for _ in 0..plies {
+            let Some(m) = book.pick(&game, &mut seed) else {
+                break;
+            };
+
+            let mut next = game.clone();
+            next.make_move(m);
+            if !seen.insert(next.hash()) {
+                break;
+            }
+
+            game = next;
+        }
+
+        Ok(Self {
+            fen: STARTPOS.to_string(),
+            id: None,
+            moves: game.moves().to_vec(),
+        })
+    }
+
+    /// A game from this opening, with the book moves already played
+    pub fn game(&self, variant: Variant) -> Game {
+        let mut game = Game::new(&self.fen, variant).unwrap();
+
+        for m in self.moves.iter() {
+            game.make_move(*m);
+        }
+
+        game
+    }
+}
+
+// tags and moves of every game, skipping comments, variations, NAGs and move numbers
+fn read_pgn(content: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    let mut chars = content.chars().peekable();
+    let mut variations = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                chars.by_ref().find(|&c| c == '}');
+            }
+            ';' | '%' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '(' => variations += 1,
+            ')' => variations -= 1,
+            '[' if variations == 0 => {
+                let tag = chars.by_ref().take_while(|&c| c != ']').collect::<String>();
+
+                if !game.moves.is_empty() {
+                    games.push(std::mem::take(&mut game));
+                }
+
+                if let Some((key, value)) = tag.trim().split_once(char::is_whitespace) {
+                    let value = value.trim().trim_matches('"').replace("\\\"", "\"");
+                    game.tags.push((key.to_string(), value));
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}();[]".contains(c) {
+                        break;
+                    }
+
+                    token.push(c);
+                    chars.next();
+                }
+
+                if variations != 0 || token.starts_with('$') {
+                    continue;
+                }
+
+                if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+                    games.push(std::mem::take(&mut game));
+                    continue;
+                }
+
+                // move numbers, possibly run together with the move as in `1.e4`
+                let token = match token.split_once('.') {
+                    Some((n, m)) if n.bytes().all(|c| c.is_ascii_digit()) => m.trim_start_matches('.'),
+                    _ => &token,
+                };
+
+                if !token.is_empty() {
+                    game.moves.push(token.to_string());
+                }
+            }
+        }
+    }
+
+    if !game.moves.is_empty() || !game.tags.is_empty() {
+        games.push(game);
+    }
+
+    games
+}
+
+/// EPD operations as `opcode operand;`, with quotes around the operand removed
+fn operations(s: &str) -> Vec<(&str, String)> {
+    let mut ops = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                ops.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    ops.push(&s[start..]);
+
+    ops.into_iter()
+        .filter_map(|op| {
+            let (opcode, operand) = op.trim().split_once(char::is_whitespace)?;
+            let operand = operand.trim();
+            let operand = operand
+                .strip_prefix('"')
+                .and_then(|o| o.strip_suffix('"'))
+                .unwrap_or(operand);
+
+            Some((opcode, operand.to_string()))
+        })
+        .collect()
+}
+
+/// Reads the first `n` openings from `file`, exiting with an error if any of them is invalid.
+/// A `.pgn` file gives the first `plies` moves of each game and a Polyglot `.bin` book `n` lines
+/// of up to `plies` moves picked with `seed`, any other file a FEN or EPD per line.
+pub fn load(file: &str, n: usize, plies: usize, seed: i32, variant: Variant) -> Vec<Opening> {
+    if file.ends_with(".bin") {
+        if n == usize::MAX {
+            eprintln!("\x1b[1;31mError:\x1b[0m {file}: `-n` is needed to pick openings from a book");
+            std::process::exit(1);
+        }
+
+        let book = crate::book::Book::open(file).unwrap_or_else(|e| {
+            eprintln!("\x1b[1;31mError:\x1b[0m {e}");
+            std::process::exit(1);
+        });
+
+        // a seed of its own for each opening, never 0 where xorshift would get stuck
+        return (0..n as u32)
+            .map(|i| {
+                let seed = (seed ^ (i + 1).wrapping_mul(0x9e37_79b9) as i32) | 1;
+
+                Opening::from_book(&book, plies, seed, variant).unwrap_or_else(|e| {
+                    eprintln!("\x1b[1;31mError:\x1b[0m {file}: {e}");
+                    std::process::exit(1);
+                })
+            })
+            .collect();
+    }
+
+    let content = std::fs::read_to_string(file).unwrap_or_else(|e| {
+        eprintln!("\x1b[1;31mError:\x1b[0m {file}: {e}");
+        std::process::exit(1);
+    });
+
+    if file.ends_with(".pgn") {
+        return read_pgn(&content)
+            .iter()
+            .take(n)
+            .enumerate()
+            .map(|(i, g)| {
+                Opening::from_pgn(g, plies, variant).unwrap_or_else(|e| {
+                    eprintln!("\x1b[1;31mError:\x1b[0m {file}: game {}: {e}", i + 1);
+                    std::process::exit(1);
+                })
+            })
+            .collect();
+    }
+
+    content
+        .trim()
+        .lines()
+        .take(n)
+        .enumerate()
+        .map(|(i, l)| {
+            Opening::parse(l, variant).unwrap_or_else(|e| {
+                eprintln!("\x1b[1;31mError:\x1b[0m {file}:{}: {e}", i + 1);
+                std::process::exit(1);
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epd_and_fen_lines() {
+        let o = Opening::parse(r#"4k3/8/8/8/8/8/8/4K3 b - - hmvc 3; fmvn 20; id "a;b";"#, Variant::Standard).unwrap();
+        assert_eq!(o.fen, "4k3/8/8/8/8/8/8/4K3 b - - 3 20");
+        assert_eq!(o.id.as_deref(), Some("a;b"));
+
+        let o = Opening::parse(r#"4k3/8/8/8/8/8/8/4K3 b - - 5 30 id "e4";"#, Variant::Standard).unwrap();
+        assert_eq!(o.fen, "4k3/8/8/8/8/8/8/4K3 b - - 5 30");
+        assert_eq!(o.id.as_deref(), Some("e4"));
+
+        let o = Opening::parse("4k3/8/8/8/8/8/8/4K3 w - -", Variant::Standard).unwrap();
+        assert_eq!(o.fen, "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(o.id, None);
+    }
+}
@@ -1,11 +1,40 @@
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::*;
 
-pub fn export_pgn(game: &chess::Game, w: &str, b: &str, fen: &str, elo: Option<(f32, f32)>) -> String {
+use crate::engine::{MoveRecord, Score};
+
+/// A name for a new game that is unique within this run, used for its PGN and logs
+pub fn game_id() -> String {
+    static GAMES: AtomicUsize = AtomicUsize::new(0);
+
+    format!(
+        "{}_{}",
+        UNIX_EPOCH.elapsed().unwrap().as_millis(),
+        GAMES.fetch_add(1, Ordering::Relaxed),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn export_pgn(
+    id: &str,
+    game: &crate::game::Game,
+    w: &str,
+    b: &str,
+    opening: &crate::openings::Opening,
+    elo: Option<(f32, f32)>,
+    moves: &[MoveRecord],
+    tc: [crate::tc::TimeControl; 2], // w | b
+    termination: crate::Termination,
+    detail: Option<&str>,
+) -> String {
     use std::fmt::Write as _;
 
     let mut pgn = String::new();
-    writeln!(pgn, r#"[Event "AB test"]"#).unwrap();
+    match opening.id.as_deref() {
+        Some(id) => writeln!(pgn, r#"[Event "AB test ({id})"]"#).unwrap(),
+        None => writeln!(pgn, r#"[Event "AB test"]"#).unwrap(),
+    }
     writeln!(pgn, r#"[Site "https://github.com/funnsam/uci-ab-test"]"#).unwrap();
     writeln!(pgn, r#"[Date "??"]"#).unwrap();
     writeln!(pgn, r#"[Round "??"]"#).unwrap();
@@ -16,137 +45,327 @@ pub fn export_pgn(game: &chess::Game, w: &str, b: &str, fen: &str, elo: Option<(
         writeln!(pgn, r#"[BlackElo "{b_elo:.0}"]"#).unwrap();
     }
 
-    let result = match game.result() {
-        Some(chess::GameResult::BlackResigns | chess::GameResult::WhiteCheckmates) => "1-0",
-        Some(chess::GameResult::WhiteResigns | chess::GameResult::BlackCheckmates) => "0-1",
-        Some(_) => "1/2-1/2",
-        None => "*",
-    };
+    let result = result(game);
 
     writeln!(pgn, r#"[Result "{result}"]"#).unwrap();
-    writeln!(pgn, r#"[FEN "{fen}"]"#).unwrap();
+    writeln!(pgn, r#"[FEN "{}"]"#, opening.fen).unwrap();
+    if let Some(id) = opening.id.as_deref() {
+        writeln!(pgn, r#"[Opening "{id}"]"#).unwrap();
+    }
+    if game.variant() == crate::game::Variant::Chess960 {
+        writeln!(pgn, r#"[Variant "Chess960"]"#).unwrap();
+    }
+    if tc[0] == tc[1] {
+        writeln!(pgn, r#"[TimeControl "{}"]"#, tc[0]).unwrap();
+    } else {
+        writeln!(pgn, r#"[WhiteTimeControl "{}"]"#, tc[0]).unwrap();
+        writeln!(pgn, r#"[BlackTimeControl "{}"]"#, tc[1]).unwrap();
+    }
+    writeln!(pgn, r#"[Termination "{}"]"#, termination.pgn_tag()).unwrap();
     writeln!(pgn).unwrap();
 
-    let mut board = chess::Board::from_str(fen).unwrap();
-
-    for (i, m) in game
-        .actions()
-        .iter()
-        .filter_map(|a| match a {
-            chess::Action::MakeMove(m) => Some(m),
-            _ => None,
-        })
-        .collect::<Vec<&chess::ChessMove>>()
-        .chunks(2)
-        .enumerate()
-    {
-        if pgn.lines().last().unwrap().len() + ((i + 1).ilog10() + 2) as usize >= 100 {
-            pgn = pgn.trim_end().to_string();
-            writeln!(pgn).unwrap();
-        }
-
-        write!(pgn, "{}. ", i + 1).unwrap();
-
-        for m in m {
-            let m = make_san(&mut board, **m);
-
-            if pgn.lines().last().unwrap().len() + m.len() >= 100 {
-                pgn = pgn.trim_end().to_string();
-                writeln!(pgn).unwrap();
+    let mut replay = game.restart();
+    let mut movenumber = opening
+        .fen
+        .split_whitespace()
+        .nth(5)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1);
+
+    for (i, m) in game.moves().iter().enumerate() {
+        let board = replay.current_position();
+        let color = board.side_to_move();
+
+        if color == chess::Color::White {
+            push_token(&mut pgn, &format!("{movenumber}."));
+        } else {
+            if i == 0 {
+                push_token(&mut pgn, &format!("{movenumber}..."));
             }
 
-            write!(pgn, "{m} ").unwrap();
+            movenumber += 1;
+        }
+        replay.make_move(*m);
+        push_token(&mut pgn, &make_san(&board, &replay.current_position(), *m));
+
+        // the engines' moves come after the opening's
+        match i.checked_sub(opening.moves.len()) {
+            Some(i) => {
+                if let Some(r) = moves.get(i) {
+                    push_token(&mut pgn, &comment(r, color));
+                }
+            }
+            None => push_token(&mut pgn, "{book}"),
         }
     }
 
+    if let Some(detail) = detail {
+        push_token(&mut pgn, &format!("{{{detail}}}"));
+    }
+
     pgn += result;
 
-    let filename = format!("game_{}.pgn", UNIX_EPOCH.elapsed().unwrap().as_millis());
+    let filename = format!("game_{id}.pgn");
 
     std::fs::write(&filename, pgn).unwrap();
 
     filename
 }
 
-fn make_san(board: &mut chess::Board, m: chess::ChessMove) -> String {
-    let cr = board.my_castle_rights();
-    if m.get_source() == board.king_square(board.side_to_move())
-        && !matches!(m.get_dest().get_file(), chess::File::D | chess::File::G)
-        && matches!(
-            m.get_dest().get_rank(),
-            chess::Rank::First | chess::Rank::Eighth
-        )
-    {
-        if m.get_dest().get_file() < m.get_source().get_file() && cr.has_queenside() {
-            *board = board.make_move_new(m);
+pub fn result(game: &crate::game::Game) -> &'static str {
+    match game.result() {
+        Some(chess::GameResult::BlackResigns | chess::GameResult::WhiteCheckmates) => "1-0",
+        Some(chess::GameResult::WhiteResigns | chess::GameResult::BlackCheckmates) => "0-1",
+        Some(_) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+fn push_token(pgn: &mut String, token: &str) {
+    if pgn.lines().last().unwrap().len() + token.len() >= 100 {
+        *pgn = pgn.trim_end().to_string();
+        pgn.push('\n');
+    }
+
+    *pgn += token;
+    pgn.push(' ');
+}
 
-            return "O-O-O".to_string();
-        } else if cr.has_kingside() {
-            *board = board.make_move_new(m);
+// cutechess-style `{+0.35/18 1.2s}` with the `%eval` and `%clk` commands that lichess and
+// SCID understand
+fn comment(r: &MoveRecord, color: chess::Color) -> String {
+    let mut c = String::from("{");
 
-            return "O-O".to_string();
+    if let Some(score) = r.info.score {
+        c += &match score {
+            Score::Cp(cp) => format!("{:+.2}", cp as f32 / 100.0),
+            Score::Mate(m) if m < 0 => format!("-M{}", -m),
+            Score::Mate(m) => format!("+M{m}"),
+        };
+
+        if let Some(depth) = r.info.depth {
+            c += &format!("/{depth}");
         }
+
+        c += " ";
+    }
+
+    if r.time < 1000 {
+        c += &format!("{:.3}s", r.time as f32 / 1000.0);
+    } else {
+        c += &format!("{:.1}s", r.time as f32 / 1000.0);
+    }
+
+    if let Some(score) = r.info.score {
+        // engines report scores for the side to move, `%eval` is always from white's view
+        let sign = if color == chess::Color::White { 1 } else { -1 };
+
+        c += &match score {
+            Score::Cp(cp) => format!(" [%eval {:.2}]", (sign * cp) as f32 / 100.0),
+            Score::Mate(m) => format!(" [%eval #{}]", sign * m),
+        };
     }
 
+    if let Some(clock) = r.clock {
+        let clock = clock / 100; // tenths of a second
+        c += &format!(
+            " [%clk {}:{:02}:{:02}.{}]",
+            clock / 36000,
+            clock / 600 % 60,
+            clock / 10 % 60,
+            clock % 10,
+        );
+    }
+
+    c += "}";
+
+    c
+}
+
+/// SAN of `m` from `board` to `next`. Castling is the king moving two squares in standard chess
+/// and the king taking its own rook in Chess960.
+fn make_san(board: &chess::Board, next: &chess::Board, m: chess::ChessMove) -> String {
     let mut san = String::new();
 
     let piece = board.piece_on(m.get_source()).unwrap();
-    if piece != chess::Piece::Pawn {
-        san += &piece.to_string(chess::Color::White);
-    };
+    let (src, dst) = (m.get_source().get_file(), m.get_dest().get_file());
 
-    if piece != chess::Piece::Pawn {
-        let mask = chess::BitBoard::from_square(m.get_dest());
-        let mut pieces = chess::BitBoard::new(0);
+    if piece == chess::Piece::King
+        && (board.color_on(m.get_dest()) == Some(board.side_to_move())
+            || src.to_index().abs_diff(dst.to_index()) == 2)
+    {
+        san += if dst > src { "O-O" } else { "O-O-O" };
+    } else {
+        if piece != chess::Piece::Pawn {
+            san += &piece.to_string(chess::Color::White);
+
+            let mask = chess::BitBoard::from_square(m.get_dest());
+            let mut pieces = chess::BitBoard::new(0);
+
+            let mut g = chess::MoveGen::new_legal(board);
+            g.set_iterator_mask(mask);
+            for m in g {
+                if board.piece_on(m.get_source()).unwrap() == piece {
+                    pieces |= chess::BitBoard::from_square(m.get_source());
+                }
+            }
 
-        let mut g = chess::MoveGen::new_legal(board);
-        g.set_iterator_mask(mask);
-        for m in g {
-            if board.piece_on(m.get_source()).unwrap() == piece {
-                pieces |= chess::BitBoard::from_square(m.get_source());
+            pieces &= !(chess::BitBoard::from_square(m.get_source()));
+
+            if pieces.0 != 0 {
+                if (pieces & chess::get_file(m.get_source().get_file())).0 == 0 {
+                    san.push((b'a' + m.get_source().get_file() as u8) as char);
+                } else if (pieces & chess::get_rank(m.get_source().get_rank())).0 == 0 {
+                    san.push((b'1' + m.get_source().get_rank() as u8) as char);
+                } else {
+                    san += &m.get_source().to_string();
+                }
             }
         }
 
-        pieces &= !(chess::BitBoard::from_square(m.get_source()));
+        // en passant
+        let captured = board.combined().popcnt() != next.combined().popcnt();
 
-        if pieces.0 != 0 {
-            if (pieces & chess::get_file(m.get_source().get_file())).0 == 0 {
+        if captured {
+            if piece == chess::Piece::Pawn {
                 san.push((b'a' + m.get_source().get_file() as u8) as char);
-            } else if (pieces & chess::get_rank(m.get_source().get_rank())).0 == 0 {
-                san.push((b'1' + m.get_source().get_rank() as u8) as char);
-            } else {
-                san += &m.get_source().to_string();
             }
+
+            san += "x";
+        }
+
+        san += &m.get_dest().to_string();
+
+        if let Some(p) = m.get_promotion() {
+            san += "=";
+            san += &p.to_string(chess::Color::White);
         }
     }
 
-    let next = board.make_move_new(m);
+    if matches!(next.status(), chess::BoardStatus::Checkmate) {
+        san += "#";
+    } else if next.checkers().0 != 0 {
+        san += "+";
+    }
 
-    // en passant
-    let captured = board.combined().popcnt() != next.combined().popcnt();
+    san
+}
 
-    if captured {
-        if piece == chess::Piece::Pawn {
-            san.push((b'a' + m.get_source().get_file() as u8) as char);
+/// The legal move written as `san` in `game`, with any check marks and annotations ignored
+pub fn parse_san(game: &crate::game::Game, san: &str) -> Option<chess::ChessMove> {
+    let board = game.current_position();
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+
+    if let Some(long) = match san {
+        "O-O" | "0-0" => Some(false),
+        "O-O-O" | "0-0-0" => Some(true),
+        _ => None,
+    } {
+        // the king taking one of its rooks on that side, or moving two squares in standard chess
+        let king = board.king_square(board.side_to_move());
+        let side = |sq: chess::Square| (sq.get_file() < king.get_file()) == long;
+        let rooks = *board.pieces(chess::Piece::Rook)
+            & board.color_combined(board.side_to_move())
+            & chess::get_rank(king.get_rank());
+
+        return rooks
+            .filter(|&r| side(r))
+            .chain(if long { king.left().and_then(|s| s.left()) } else { king.right().and_then(|s| s.right()) })
+            .map(|dst| chess::ChessMove::new(king, dst, None))
+            .find(|&m| game.legal(m));
+    }
+
+    let (piece, rest) = match san.chars().next()? {
+        'N' => (chess::Piece::Knight, &san[1..]),
+        'B' => (chess::Piece::Bishop, &san[1..]),
+        'R' => (chess::Piece::Rook, &san[1..]),
+        'Q' => (chess::Piece::Queen, &san[1..]),
+        'K' => (chess::Piece::King, &san[1..]),
+        _ => (chess::Piece::Pawn, san),
+    };
+
+    let (rest, promotion) = match rest.as_bytes() {
+        [.., b'=' | b'1'..=b'8', p @ (b'N' | b'B' | b'R' | b'Q')] => {
+            let promotion = match p {
+                b'N' => chess::Piece::Knight,
+                b'B' => chess::Piece::Bishop,
+                b'R' => chess::Piece::Rook,
+                _ => chess::Piece::Queen,
+            };
+
+            (rest[..rest.len() - 1].trim_end_matches('='), Some(promotion))
         }
+        _ => (rest, None),
+    };
+
+    let dest = chess::Square::from_str(rest.get(rest.len().checked_sub(2)?..)?).ok()?;
+    let from = rest[..rest.len() - 2].trim_end_matches('x');
+
+    // disambiguation by file, rank or both
+    let file = from.bytes().find(|c| c.is_ascii_lowercase()).map(|c| (c - b'a') as usize);
+    let rank = from.bytes().find(|c| c.is_ascii_digit()).map(|c| (c - b'1') as usize);
 
-        san += "x";
+    let mut moves = chess::MoveGen::new_legal(&board).filter(|m| {
+        m.get_dest() == dest
+            && m.get_promotion() == promotion
+            && board.piece_on(m.get_source()) == Some(piece)
+            && file.is_none_or(|f| m.get_source().get_file().to_index() == f)
+            && rank.is_none_or(|r| m.get_source().get_rank().to_index() == r)
+    });
+
+    match (moves.next(), moves.next()) {
+        (Some(m), None) => Some(m),
+        _ => None,
     }
+}
 
-    san += &m.get_dest().to_string();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Game, Variant};
 
-    if let Some(p) = m.get_promotion() {
-        san += "=";
-        san += &p.to_string(chess::Color::White);
+    // the move `san` parses to in `fen` as the game records it, checking that it is written back
+    // the same way
+    fn parse(fen: &str, variant: Variant, san: &str) -> Option<String> {
+        let game = Game::new(fen, variant).unwrap();
+        let m = parse_san(&game, san)?;
+
+        let mut next = game.clone();
+        next.make_move(m);
+        assert_eq!(make_san(&game.current_position(), &next.current_position(), m), san.replace('0', "O"));
+
+        Some(next.moves()[0].to_string())
     }
 
-    if matches!(next.status(), chess::BoardStatus::Checkmate) {
-        san += "#";
-    } else if next.checkers().0 != 0 {
-        san += "+";
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(parse(fen, Variant::Standard, "O-O").as_deref(), Some("e1g1"));
+        assert_eq!(parse(fen, Variant::Standard, "0-0-0").as_deref(), Some("e1c1"));
+        assert_eq!(parse(fen, Variant::Chess960, "O-O").as_deref(), Some("e1h1"));
+        assert_eq!(parse(fen, Variant::Chess960, "O-O-O").as_deref(), Some("e1a1"));
+
+        // the king next to its rook
+        let fen = "4k3/8/8/8/8/8/8/R5KR w HA - 0 1";
+        assert_eq!(parse(fen, Variant::Chess960, "O-O").as_deref(), Some("g1h1"));
     }
 
-    *board = next;
+    #[test]
+    fn promotion() {
+        let fen = "1n6/P7/8/8/8/7k/8/7K w - - 0 1";
+        assert_eq!(parse(fen, Variant::Standard, "a8=Q").as_deref(), Some("a7a8q"));
+        assert_eq!(parse(fen, Variant::Standard, "axb8=N").as_deref(), Some("a7b8n"));
+        assert_eq!(parse_san(&Game::new(fen, Variant::Standard).unwrap(), "a8"), None);
+    }
 
-    san
+    #[test]
+    fn disambiguation() {
+        let fen = "k7/8/8/8/8/1N6/8/KN3N2 w - - 0 1";
+        assert_eq!(parse(fen, Variant::Standard, "Nfd2").as_deref(), Some("f1d2"));
+        assert_eq!(parse(fen, Variant::Standard, "Nb1d2").as_deref(), Some("b1d2"));
+        assert_eq!(parse(fen, Variant::Standard, "N3d2").as_deref(), Some("b3d2"));
+        assert_eq!(parse_san(&Game::new(fen, Variant::Standard).unwrap(), "Nd2"), None);
+        assert_eq!(parse_san(&Game::new(fen, Variant::Standard).unwrap(), "Nbd2"), None);
+        assert_eq!(parse_san(&Game::new(fen, Variant::Standard).unwrap(), "N1d2"), None);
+    }
 }
@@ -0,0 +1,80 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+type Job<T> = Box<dyn FnOnce() -> T + Send>;
+
+/// A fixed number of worker threads fed from a job queue. Finished jobs are handed back
+/// through [`Pool::recv`] in completion order.
+pub struct Pool<T> {
+    jobs: Option<mpsc::Sender<Job<T>>>,
+    results: mpsc::Receiver<Option<T>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: usize,
+}
+
+impl<T: Send + 'static> Pool<T> {
+    pub fn new(size: usize) -> Self {
+        let (jobs, job_rx) = mpsc::channel::<Job<T>>();
+        let (result_tx, results) = mpsc::channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let job_rx = Arc::clone(&job_rx);
+                let result_tx = result_tx.clone();
+
+                std::thread::spawn(move || loop {
+                    let Ok(job) = job_rx.lock().unwrap().recv() else {
+                        break;
+                    };
+
+                    // a panicking job still has to be accounted for or `recv` would wait forever
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).ok();
+                    if result_tx.send(result).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+            pending: 0,
+        }
+    }
+
+    /// Whether every worker already has a job, i.e. whether a new job would have to queue
+    pub fn is_full(&self) -> bool {
+        self.pending >= self.workers.len()
+    }
+
+    pub fn submit(&mut self, job: impl FnOnce() -> T + Send + 'static) {
+        self.pending += 1;
+        self.jobs.as_ref().unwrap().send(Box::new(job)).unwrap();
+    }
+
+    /// Blocks until the next job finishes. Returns `None` once no jobs are left.
+    pub fn recv(&mut self) -> Option<T> {
+        while self.pending != 0 {
+            self.pending -= 1;
+
+            if let Some(result) = self.results.recv().unwrap() {
+                return Some(result);
+            }
+        }
+
+        None
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        self.jobs.take();
+
+        for w in self.workers.drain(..) {
+            _ = w.join();
+        }
+    }
+}
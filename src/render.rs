@@ -10,7 +10,7 @@ pub fn render(board: &Board, w_name: &str, b_name: &str, last_move: Option<Chess
             let piece = board.piece_on(square);
             let color = board.color_on(square);
             let parity = (rank.to_index() + file.to_index()) & 1 == 1;
-            let highlight = last_move.map_or(false, |m| square == m.get_source() || square == m.get_dest());
+            let highlight = last_move.is_some_and(|m| square == m.get_source() || square == m.get_dest());
 
             print!("\x1b[{}{}", if matches!(piece, Some(Piece::King)) && color == Some(board.side_to_move()) && board.checkers().0 != 0 {
                 "48;2;235;94;78"
@@ -0,0 +1,104 @@
+const PSEUDO_COUNT: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hypothesis {
+    H0,
+    H1,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64, alpha: f64, beta: f64) -> Self {
+        Self { elo0, elo1, alpha, beta }
+    }
+
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    /// Generalized SPRT log-likelihood ratio using the normal approximation of the given
+    /// score distribution
+    pub fn llr(&self, dist: &[(f64, usize)]) -> f64 {
+        let Some((score, _, n)) = crate::stats::mean_variance(dist) else {
+            return 0.0;
+        };
+
+        // the variance with half a game added to every outcome, so that one-sided results such
+        // as nothing but wins still have some spread and can end the test
+        let count = |c: usize| c as f64 + PSEUDO_COUNT;
+        let variance = dist
+            .iter()
+            .map(|&(s, c)| (s - score).powi(2) * count(c))
+            .sum::<f64>()
+            / dist.iter().map(|&(_, c)| count(c)).sum::<f64>();
+
+        let s0 = expected_score(self.elo0);
+        let s1 = expected_score(self.elo1);
+
+        (s1 - s0) * (2.0 * score - s0 - s1) / (2.0 * variance / n as f64)
+    }
+
+    pub fn status(&self, llr: f64) -> Option<Hypothesis> {
+        let (lower, upper) = self.bounds();
+
+        if llr <= lower {
+            Some(Hypothesis::H0)
+        } else if llr >= upper {
+            Some(Hypothesis::H1)
+        } else {
+            None
+        }
+    }
+
+    pub fn report(&self, llr: f64) -> String {
+        let (lower, upper) = self.bounds();
+
+        format!(
+            "LLR {llr:.2} ({lower:.2}, {upper:.2}) [{:.2}, {:.2}]",
+            self.elo0, self.elo1
+        )
+    }
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10.0_f64.powf(-elo / 400.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(w: usize, d: usize, l: usize) -> Option<Hypothesis> {
+        let sprt = Sprt::new(0.0, 5.0, 0.05, 0.05);
+        sprt.status(sprt.llr(&[(1.0, w), (0.5, d), (0.0, l)]))
+    }
+
+    #[test]
+    fn one_sided_results_stop() {
+        assert_eq!(status(60, 0, 0), Some(Hypothesis::H1));
+        assert_eq!(status(0, 0, 60), Some(Hypothesis::H0));
+        assert_eq!(status(0, 1000, 0), Some(Hypothesis::H0));
+
+        // 60 games won as 30 pairs
+        let sprt = Sprt::new(0.0, 5.0, 0.05, 0.05);
+        let pairs = [(0.0, 0), (0.25, 0), (0.5, 0), (0.75, 0), (1.0, 30)];
+        assert_eq!(sprt.status(sprt.llr(&pairs)), Some(Hypothesis::H1));
+    }
+
+    #[test]
+    fn few_games_are_inconclusive() {
+        assert_eq!(status(0, 0, 0), None);
+        assert_eq!(status(1, 1, 1), None);
+        assert_eq!(status(5, 0, 0), None);
+    }
+}
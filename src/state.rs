@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crate::engine::UciOption;
+use crate::game::Variant;
+use crate::sprt::Sprt;
+use crate::tc::TimeControl;
+use crate::Termination;
+
+// A match state file is a few `key value` header lines followed by one `game` line per finished
+// game, appended as soon as the game is done so that a killed match loses nothing.
+
+#[derive(Debug, Clone)]
+pub struct MatchInfo {
+    pub a: String,
+    pub b: String,
+    pub tc: [TimeControl; 2], // a | b
+    pub opening_positions: String,
+    pub play_positions: usize,
+    pub plies: usize,
+    pub seed: i32,
+    pub biased: bool,
+    pub a_options: Vec<UciOption>,
+    pub b_options: Vec<UciOption>,
+    pub variant: Variant,
+    pub elos: (f32, f32), // a | b
+    pub sprt: Option<Sprt>,
+}
+
+pub struct MatchState {
+    pub info: MatchInfo,
+    pub games: Vec<(usize, bool, f64, Termination)>, // opening index | colors swapped | score of a | how it ended
+    done: HashSet<(usize, bool)>,
+    file: std::fs::File,
+}
+
+impl MatchState {
+    /// Starts a new state file at `path`, which must not exist yet so that no earlier match is
+    /// overwritten
+    pub fn create(path: &str, info: MatchInfo) -> Result<Self, String> {
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => format!("{path} already exists, use --resume to continue it"),
+                _ => format!("{path}: {e}"),
+            })?;
+        let mut header = String::new();
+
+        writeln!(header, "a {}", info.a).unwrap();
+        writeln!(header, "b {}", info.b).unwrap();
+        writeln!(header, "a-tc {}", info.tc[0]).unwrap();
+        writeln!(header, "b-tc {}", info.tc[1]).unwrap();
+        writeln!(header, "openings {}", info.opening_positions).unwrap();
+        writeln!(header, "positions {}", info.play_positions).unwrap();
+        writeln!(header, "plies {}", info.plies).unwrap();
+        writeln!(header, "seed {}", info.seed).unwrap();
+        writeln!(header, "biased {}", info.biased).unwrap();
+        writeln!(header, "variant {}", info.variant).unwrap();
+        writeln!(header, "a-elo {}", info.elos.0).unwrap();
+        writeln!(header, "b-elo {}", info.elos.1).unwrap();
+        if let Some(s) = info.sprt {
+            writeln!(header, "sprt {} {} {} {}", s.elo0, s.elo1, s.alpha, s.beta).unwrap();
+        }
+        for o in info.a_options.iter() {
+            writeln!(header, "a-option {o}").unwrap();
+        }
+        for o in info.b_options.iter() {
+            writeln!(header, "b-option {o}").unwrap();
+        }
+        file.write_all(header.as_bytes())
+            .and_then(|_| file.flush())
+            .map_err(|e| format!("{path}: {e}"))?;
+
+        Ok(Self {
+            info,
+            games: Vec::new(),
+            done: HashSet::new(),
+            file,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+
+        let mut a = None;
+        let mut b = None;
+        let mut time = None;
+        let mut inc = None;
+        let mut tc = [None; 2];
+        let mut opening_positions = None;
+        let mut play_positions = None;
+        let mut plies = usize::MAX;
+        let mut seed = 1;
+        let mut biased = None;
+        let mut a_options = Vec::new();
+        let mut b_options = Vec::new();
+        let mut variant = Variant::Standard; // older state files are all standard chess
+        let mut elos = (1200.0, 1200.0);
+        let mut sprt = None;
+        let mut games = Vec::new();
+
+        for (n, l) in content.lines().enumerate() {
+            let err = || format!("{path}:{}: malformed line `{l}`", n + 1);
+            let (key, value) = l.split_once(' ').ok_or_else(err)?;
+
+            match key {
+                "a" => a = Some(value.to_string()),
+                "b" => b = Some(value.to_string()),
+                "time" => time = Some(value.parse().map_err(|_| err())?),
+                "inc" => inc = Some(value.parse().map_err(|_| err())?),
+                "tc" => tc = [Some(value.parse().map_err(|_| err())?); 2],
+                "a-tc" => tc[0] = Some(value.parse().map_err(|_| err())?),
+                "b-tc" => tc[1] = Some(value.parse().map_err(|_| err())?),
+                "openings" => opening_positions = Some(value.to_string()),
+                "positions" => play_positions = Some(value.parse().map_err(|_| err())?),
+                "plies" => plies = value.parse().map_err(|_| err())?,
+                "seed" => seed = value.parse().map_err(|_| err())?,
+                "biased" => biased = Some(value.parse().map_err(|_| err())?),
+                "variant" => variant = value.parse().map_err(|_| err())?,
+                "a-elo" => elos.0 = value.parse().map_err(|_| err())?,
+                "b-elo" => elos.1 = value.parse().map_err(|_| err())?,
+                "sprt" => {
+                    let s = value
+                        .split_whitespace()
+                        .map(|s| s.parse())
+                        .collect::<Result<Vec<f64>, _>>()
+                        .map_err(|_| err())?;
+
+                    match s[..] {
+                        [elo0, elo1, alpha, beta] => sprt = Some(Sprt::new(elo0, elo1, alpha, beta)),
+                        _ => return Err(err()),
+                    }
+                }
+                "a-option" => a_options.push(value.parse().map_err(|_| err())?),
+                "b-option" => b_options.push(value.parse().map_err(|_| err())?),
+                "game" => {
+                    let mut tokens = value.split_whitespace();
+                    let mut next = || tokens.next().ok_or_else(err);
+
+                    let opening = next()?.parse().map_err(|_| err())?;
+                    let polarity = next()? == "1";
+                    let score = next()?.parse().map_err(|_| err())?;
+                    // older state files don't record how games ended
+                    let termination = match tokens.next() {
+                        Some(t) => t.parse().map_err(|_| err())?,
+                        None => Termination::Normal,
+                    };
+
+                    games.push((opening, polarity, score, termination));
+                }
+                _ => return Err(err()),
+            }
+        }
+
+        let missing = |key: &str| format!("{path}: missing `{key}`");
+        // older state files have a shared time and increment in milliseconds instead
+        let tc = match (tc, time, inc) {
+            ([Some(a), Some(b)], _, _) => [a, b],
+            ([None, None], Some(time), Some(inc)) => [TimeControl::fischer(time, inc); 2],
+            ([_, None], ..) => return Err(missing("b-tc")),
+            _ => return Err(missing("a-tc")),
+        };
+        let info = MatchInfo {
+            a: a.ok_or_else(|| missing("a"))?,
+            b: b.ok_or_else(|| missing("b"))?,
+            tc,
+            opening_positions: opening_positions.ok_or_else(|| missing("openings"))?,
+            play_positions: play_positions.ok_or_else(|| missing("positions"))?,
+            plies,
+            seed,
+            biased: biased.ok_or_else(|| missing("biased"))?,
+            a_options,
+            b_options,
+            variant,
+            elos,
+            sprt,
+        };
+
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{path}: {e}"))?;
+
+        let done = games.iter().map(|&(o, p, _, _)| (o, p)).collect();
+
+        Ok(Self {
+            info,
+            games,
+            done,
+            file,
+        })
+    }
+
+    pub fn add(&mut self, opening: usize, polarity: bool, score: f64, termination: Termination) {
+        self.games.push((opening, polarity, score, termination));
+        self.done.insert((opening, polarity));
+
+        writeln!(self.file, "game {opening} {} {score} {}", polarity as u8, termination.name()).unwrap();
+        self.file.flush().unwrap();
+    }
+
+    pub fn is_done(&self, opening: usize, polarity: bool) -> bool {
+        self.done.contains(&(opening, polarity))
+    }
+}
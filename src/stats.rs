@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::Termination;
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub wdl: [usize; 3],   // a win | draw | b win
+    pub penta: [usize; 5], // LL | LD | DD+WL | WD | WW (for a)
+    pub terminations: BTreeMap<Termination, usize>,
+    forfeits: BTreeMap<Termination, [usize; 2]>, // losses of a | b
+    pending: HashMap<usize, f64>,
+}
+
+/// Search statistics of one engine accumulated over all of its moves
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SearchStats {
+    pub moves: usize,
+    depth: u64,
+    depth_moves: usize,
+    nodes: u64,
+    time: u64,
+}
+
+pub struct Estimate {
+    pub elo: f64,
+    pub error: f64,
+    pub los: f64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a finished game, `score` being the score of engine A. Both games of an opening
+    /// share the same `pair` and are counted into the pentanomial once both are done.
+    pub fn add(&mut self, pair: usize, score: f64, termination: Termination) {
+        self.wdl[score_index(score)] += 1;
+        *self.terminations.entry(termination).or_default() += 1;
+
+        // the side that forfeited is the one that lost
+        if termination.is_forfeit() {
+            self.forfeits.entry(termination).or_default()[(score > 0.5) as usize] += 1;
+        }
+
+        if let Some(other) = self.pending.remove(&pair) {
+            self.penta[((score + other) * 2.0).round() as usize] += 1;
+        } else {
+            self.pending.insert(pair, score);
+        }
+    }
+
+    pub fn games(&self) -> usize {
+        self.wdl.iter().sum()
+    }
+
+    pub fn pairs(&self) -> usize {
+        self.penta.iter().sum()
+    }
+
+    /// e.g. `normal 90, time forfeit 2, draw adjudication 8`
+    pub fn terminations(&self) -> String {
+        self.terminations
+            .iter()
+            .map(|(t, n)| format!("{t} {n}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Forfeits of a (0) or b (1), e.g. `time forfeit 2, disconnected 1`
+    pub fn forfeits(&self, side: usize) -> String {
+        let forfeits = self
+            .forfeits
+            .iter()
+            .filter(|(_, n)| n[side] != 0)
+            .map(|(t, n)| format!("{t} {}", n[side]))
+            .collect::<Vec<_>>();
+
+        if forfeits.is_empty() {
+            "none".to_string()
+        } else {
+            forfeits.join(", ")
+        }
+    }
+
+    pub fn draw_ratio(&self) -> f64 {
+        self.wdl[1] as f64 / self.games().max(1) as f64
+    }
+
+    pub fn trinomial(&self) -> [(f64, usize); 3] {
+        [(1.0, self.wdl[0]), (0.5, self.wdl[1]), (0.0, self.wdl[2])]
+    }
+
+    pub fn pentanomial(&self) -> [(f64, usize); 5] {
+        [
+            (0.0, self.penta[0]),
+            (0.25, self.penta[1]),
+            (0.5, self.penta[2]),
+            (0.75, self.penta[3]),
+            (1.0, self.penta[4]),
+        ]
+    }
+
+    /// Game pairs are used whenever there are any, since paired openings are correlated and
+    /// treating them as independent games overstates confidence.
+    pub fn distribution(&self) -> Vec<(f64, usize)> {
+        if self.pairs() != 0 {
+            self.pentanomial().to_vec()
+        } else {
+            self.trinomial().to_vec()
+        }
+    }
+
+    pub fn estimate(&self) -> Option<Estimate> {
+        let (mean, variance, n) = mean_variance(&self.distribution())?;
+        let se = (variance / n as f64).sqrt();
+
+        // without any spread the result is certain either way
+        let los = if se == 0.0 {
+            match mean.partial_cmp(&0.5).unwrap() {
+                std::cmp::Ordering::Greater => 1.0,
+                std::cmp::Ordering::Less => 0.0,
+                std::cmp::Ordering::Equal => 0.5,
+            }
+        } else {
+            0.5 * (1.0 + erf((mean - 0.5) / (std::f64::consts::SQRT_2 * se)))
+        };
+
+        Some(Estimate {
+            elo: elo(mean),
+            error: (elo(mean + 1.96 * se) - elo(mean - 1.96 * se)) / 2.0,
+            los,
+        })
+    }
+}
+
+impl SearchStats {
+    pub fn add(&mut self, info: &crate::engine::SearchInfo) {
+        self.moves += 1;
+
+        if let Some(depth) = info.depth {
+            self.depth += depth as u64;
+            self.depth_moves += 1;
+        }
+
+        // engines that don't report nodes and time get their nps weighted by the move time
+        match (info.nodes, info.time, info.nps) {
+            (Some(nodes), Some(time), _) => {
+                self.nodes += nodes;
+                self.time += time;
+            }
+            (_, Some(time), Some(nps)) => {
+                self.nodes += nps * time / 1000;
+                self.time += time;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.moves += other.moves;
+        self.depth += other.depth;
+        self.depth_moves += other.depth_moves;
+        self.nodes += other.nodes;
+        self.time += other.time;
+    }
+
+    pub fn depth(&self) -> Option<f64> {
+        (self.depth_moves != 0).then(|| self.depth as f64 / self.depth_moves as f64)
+    }
+
+    pub fn nps(&self) -> Option<f64> {
+        (self.time != 0).then(|| self.nodes as f64 * 1000.0 / self.time as f64)
+    }
+}
+
+impl std::fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.depth() {
+            Some(depth) => write!(f, "depth {depth:.1}")?,
+            None => write!(f, "depth ?")?,
+        }
+
+        match self.nps() {
+            Some(nps) => write!(f, ", {:.2} Mnps", nps / 1_000_000.0),
+            None => write!(f, ", ? Mnps"),
+        }
+    }
+}
+
+fn score_index(score: f64) -> usize {
+    if score > 0.5 {
+        0
+    } else if score < 0.5 {
+        2
+    } else {
+        1
+    }
+}
+
+pub fn mean_variance(dist: &[(f64, usize)]) -> Option<(f64, f64, usize)> {
+    let n = dist.iter().map(|(_, c)| c).sum::<usize>();
+    if n == 0 {
+        return None;
+    }
+
+    let mean = dist.iter().map(|(s, c)| s * *c as f64).sum::<f64>() / n as f64;
+    let variance = dist
+        .iter()
+        .map(|(s, c)| (s - mean).powi(2) * *c as f64)
+        .sum::<f64>()
+        / n as f64;
+
+    Some((mean, variance, n))
+}
+
+pub fn elo(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+// Abramowitz and Stegun 7.1.26
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let y = 1.0
+        - (((((1.061405429 * t - 1.453152027) * t) + 1.421413741) * t - 0.284496736) * t
+            + 0.254829592)
+            * t
+            * (-x * x).exp();
+
+    y.copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn los(scores: &[f64]) -> f64 {
+        let mut stats = Stats::new();
+        for (i, &score) in scores.iter().enumerate() {
+            stats.add(i / 2, score, Termination::Normal);
+        }
+
+        stats.estimate().unwrap().los
+    }
+
+    #[test]
+    fn los_without_spread() {
+        assert_eq!(los(&[1.0; 60]), 1.0);
+        assert_eq!(los(&[0.0; 60]), 0.0);
+        assert_eq!(los(&[0.5; 60]), 0.5);
+        assert_eq!(los(&[1.0, 0.0, 1.0, 0.0]), 0.5);
+    }
+}
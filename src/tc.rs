@@ -0,0 +1,223 @@
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+/// A time control in cutechess-cli notation: `40/60+0.6`, `60+0.6`, `st=0.1`, `depth=8`,
+/// `nodes=10000` or `inf=30`. Times are given in seconds and kept in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeControl {
+    /// `time` for every `moves` moves (or the whole game) plus `inc` per move
+    Clock {
+        moves: Option<usize>,
+        time: usize,
+        inc: usize,
+    },
+    MoveTime(usize),
+    Depth(u32),
+    Nodes(u64),
+    /// `go infinite` with a hard cap after which the engine is told to stop
+    Infinite(usize),
+}
+
+impl TimeControl {
+    pub fn fischer(time: usize, inc: usize) -> Self {
+        Self::Clock {
+            moves: None,
+            time,
+            inc,
+        }
+    }
+}
+
+fn ms(s: &str) -> Result<usize, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t >= 0.0 && t.is_finite() => Ok((t * 1000.0).round() as usize),
+        _ => Err(format!("invalid number of seconds `{s}`")),
+    }
+}
+
+fn seconds(ms: usize) -> String {
+    if ms.is_multiple_of(1000) {
+        (ms / 1000).to_string()
+    } else {
+        (ms as f64 / 1000.0).to_string()
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if let Some((key, value)) = s.split_once('=') {
+            let invalid = || format!("invalid value for `{key}`: `{value}`");
+
+            return match key {
+                "st" => Ok(Self::MoveTime(ms(value)?)),
+                "depth" => Ok(Self::Depth(value.parse().map_err(|_| invalid())?)),
+                "nodes" => Ok(Self::Nodes(value.parse().map_err(|_| invalid())?)),
+                "inf" => Ok(Self::Infinite(ms(value)?)),
+                _ => Err(format!("unknown time control `{key}`, expected st, depth, nodes or inf")),
+            };
+        }
+
+        let (moves, rest) = match s.split_once('/') {
+            Some((moves, rest)) => match moves.parse() {
+                Ok(0) | Err(_) => return Err(format!("invalid number of moves `{moves}`")),
+                Ok(moves) => (Some(moves), rest),
+            },
+            None => (None, s),
+        };
+
+        let (time, inc) = rest.split_once('+').unwrap_or((rest, "0"));
+
+        Ok(Self::Clock {
+            moves,
+            time: ms(time)?,
+            inc: ms(inc)?,
+        })
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Self::Clock { moves, time, inc } => {
+                if let Some(moves) = moves {
+                    write!(f, "{moves}/")?;
+                }
+
+                write!(f, "{}+{}", seconds(time), seconds(inc))
+            }
+            Self::MoveTime(t) => write!(f, "st={}", seconds(t)),
+            Self::Depth(d) => write!(f, "depth={d}"),
+            Self::Nodes(n) => write!(f, "nodes={n}"),
+            Self::Infinite(t) => write!(f, "inf={}", seconds(t)),
+        }
+    }
+}
+
+/// The clock of one side during a game
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    pub tc: TimeControl,
+    left: usize, // ms
+    moves: usize,
+}
+
+impl Clock {
+    pub fn new(tc: TimeControl) -> Self {
+        let left = match tc {
+            TimeControl::Clock { time, .. } => time,
+            _ => 0,
+        };
+
+        Self { tc, left, moves: 0 }
+    }
+
+    /// Time left in ms, if the time control has a clock at all
+    pub fn left(&self) -> Option<usize> {
+        matches!(self.tc, TimeControl::Clock { .. }).then_some(self.left)
+    }
+
+    pub fn movestogo(&self) -> Option<usize> {
+        match self.tc {
+            TimeControl::Clock { moves: Some(m), .. } => Some(m - self.moves % m),
+            _ => None,
+        }
+    }
+
+    /// How long the next move may take in ms, if it is limited by time at all
+    pub fn available(&self) -> Option<usize> {
+        match self.tc {
+            TimeControl::Clock { inc, .. } => Some(self.left + inc),
+            TimeControl::MoveTime(t) | TimeControl::Infinite(t) => Some(t),
+            TimeControl::Depth(_) | TimeControl::Nodes(_) => None,
+        }
+    }
+
+    /// How long a search may run before `stop` is sent
+    pub fn cap(&self) -> Option<std::time::Duration> {
+        match self.tc {
+            TimeControl::Infinite(t) => Some(std::time::Duration::from_millis(t as u64)),
+            _ => None,
+        }
+    }
+
+    /// Charges `used` ms for a move, letting it run over by up to `margin` ms. Gives the time
+    /// it overshot by if that wasn't enough.
+    pub fn spend(&mut self, used: usize, margin: usize) -> Result<(), usize> {
+        self.moves += 1;
+
+        match self.tc {
+            TimeControl::Clock { moves, time, inc } => {
+                let available = self.left + inc;
+
+                if used > available + margin {
+                    self.left = 0;
+                    return Err(used - available);
+                }
+
+                self.left = available.saturating_sub(used);
+
+                if moves.is_some_and(|m| self.moves.is_multiple_of(m)) {
+                    self.left += time;
+                }
+
+                Ok(())
+            }
+            TimeControl::MoveTime(t) if used > t + margin => Err(used - t),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The `go` command for `stm`, given the clocks of both sides (w | b)
+pub fn go(clocks: &[Clock; 2], stm: chess::Color) -> String {
+    let own = &clocks[stm.to_index()];
+
+    match own.tc {
+        TimeControl::Clock { .. } => {
+            let mut go = String::from("go");
+
+            for (side, clock) in ["w", "b"].iter().zip(clocks) {
+                if let (Some(left), TimeControl::Clock { inc, .. }) = (clock.left(), clock.tc) {
+                    write!(go, " {side}time {left} {side}inc {inc}").unwrap();
+                }
+            }
+
+            if let Some(m) = own.movestogo() {
+                write!(go, " movestogo {m}").unwrap();
+            }
+
+            go
+        }
+        TimeControl::MoveTime(t) => format!("go movetime {t}"),
+        TimeControl::Depth(d) => format!("go depth {d}"),
+        TimeControl::Nodes(n) => format!("go nodes {n}"),
+        TimeControl::Infinite(_) => "go infinite".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let tc = "40/60+0.6".parse::<TimeControl>();
+        assert_eq!(tc, Ok(TimeControl::Clock { moves: Some(40), time: 60000, inc: 600 }));
+        assert_eq!(tc.unwrap().to_string(), "40/60+0.6");
+
+        assert_eq!("10".parse(), Ok(TimeControl::fischer(10000, 0)));
+        assert_eq!("st=0.1".parse(), Ok(TimeControl::MoveTime(100)));
+        assert_eq!("depth=8".parse(), Ok(TimeControl::Depth(8)));
+        assert_eq!("nodes=10000".parse(), Ok(TimeControl::Nodes(10000)));
+        assert_eq!("inf=30".parse(), Ok(TimeControl::Infinite(30000)));
+    }
+
+    #[test]
+    fn invalid() {
+        for tc in ["", "0/60", "x/60", "60+", "-1", "st=", "depth=1.5", "nodes=-1", "foo=1", "1+nan"] {
+            assert!(tc.parse::<TimeControl>().is_err(), "{tc}");
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::*;
+
+use crate::pool::Pool;
+use crate::stats::Stats;
+use crate::Player;
+
+pub fn tournament(
+    engines: &[String],
+    options: &[crate::engine::UciOption],
+    openings: &[crate::openings::Opening],
+    config: crate::GameConfig,
+    gauntlet: bool,
+    jobs: usize,
+    mean_elo: f64,
+) {
+    let players = engines
+        .iter()
+        .map(|e| Arc::new(Player::new(e, options, &config)))
+        .collect::<Vec<_>>();
+
+    // a gauntlet pits the first engine against every other one
+    let pairings = if gauntlet {
+        (1..players.len()).map(|b| (0, b)).collect::<Vec<_>>()
+    } else {
+        (0..players.len())
+            .flat_map(|a| (a + 1..players.len()).map(move |b| (a, b)))
+            .collect::<Vec<_>>()
+    };
+
+    let mut stats = pairings.iter().map(|_| Stats::new()).collect::<Vec<_>>();
+    let config = Arc::new(config);
+    let mut pool = Pool::new(jobs);
+
+    println!("\x1b[1;32mInfo:\x1b[0m initialization complete");
+
+    for (i, opening) in openings.iter().enumerate() {
+        let game = opening.game(config.variant);
+        let opening = Arc::new(opening.clone());
+
+        for (p, &(a, b)) in pairings.iter().enumerate() {
+            for polarity in [false, true] {
+                let (a, b) = if !polarity {
+                    (Arc::clone(&players[a]), Arc::clone(&players[b]))
+                } else {
+                    (Arc::clone(&players[b]), Arc::clone(&players[a]))
+                };
+                let game = game.clone();
+                let opening = Arc::clone(&opening);
+                let config = Arc::clone(&config);
+
+                pool.submit(move || {
+                    (p, i, crate::play_single(a, b, None, game, opening, config, polarity))
+                });
+            }
+        }
+    }
+
+    while let Some((p, pair, report)) = pool.recv() {
+        stats[p].add(pair, report.score, report.termination);
+    }
+
+    let results = pairings
+        .iter()
+        .zip(stats.iter())
+        .map(|(&(a, b), s)| (a, b, s.wdl))
+        .collect::<Vec<_>>();
+    let ratings = crate::elo::estimate(players.len(), &results, mean_elo);
+
+    // (points, games) of every engine against every other one
+    let mut scores = vec![vec![None; players.len()]; players.len()];
+    for &(a, b, [w, d, l]) in results.iter() {
+        let games = w + d + l;
+        scores[a][b] = Some((w as f64 + d as f64 / 2.0, games));
+        scores[b][a] = Some((l as f64 + d as f64 / 2.0, games));
+    }
+
+    let mut ranking = (0..players.len()).collect::<Vec<_>>();
+    ranking.sort_by(|a, b| ratings.elo[*b].total_cmp(&ratings.elo[*a]));
+
+    let term_size = term_size::dimensions().unwrap_or((80, 24));
+    let indents_length = term_size.0.saturating_sub(" SUMMARY ".len());
+    let l_indent = "=".repeat(indents_length / 2);
+    let r_indent = "=".repeat(indents_length - indents_length / 2);
+
+    let name_width = players.iter().map(|p| p.name.chars().count()).max().unwrap_or(0);
+    let cell_width = format!("{0}.5/{0}", openings.len() * 2).len();
+
+    println!("\n\n\x1b[1m{} SUMMARY {}\x1b[0m", l_indent, r_indent);
+    print!("\x1b[1m  # {:name_width$}   Elo  Score", "Name");
+    for rank in 1..=players.len() {
+        print!(" {rank:>cell_width$}");
+    }
+    println!("\x1b[0m");
+
+    for (rank, &p) in ranking.iter().enumerate() {
+        let (points, games) = scores[p]
+            .iter()
+            .flatten()
+            .fold((0.0, 0), |(p, g), (points, games)| (p + points, g + games));
+
+        print!(
+            "{:>3} {:name_width$} {:>5.0} {:>5.1}%",
+            rank + 1,
+            players[p].name,
+            ratings.elo[p],
+            points * 100.0 / games.max(1) as f64,
+        );
+
+        for &o in ranking.iter() {
+            let cell = if o == p {
+                "-".to_string()
+            } else if let Some((points, games)) = scores[p][o] {
+                format!("{points}/{games}")
+            } else {
+                String::new()
+            };
+
+            print!(" {cell:>cell_width$}");
+        }
+
+        println!();
+    }
+
+    println!(
+        "\n \x1b[1mGames:\x1b[0m {}, \x1b[1mDraw elo:\x1b[0m {:.0}",
+        results.iter().map(|(_, _, wdl)| wdl.iter().sum::<usize>()).sum::<usize>(),
+        ratings.draw_elo,
+    );
+}
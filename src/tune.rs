@@ -1,16 +1,20 @@
 use std::ops::*;
-use std::str::FromStr;
-use std::sync::{*, atomic::*};
+use std::sync::*;
+
+use crate::pool::Pool;
 
 const ALPHA: f32 = 0.602;
 const GAMMA: f32 = 0.101;
 const C: f32 = 0.5;
 const MAGNITUDE: f32 = 0.1;
 
-pub fn tune(iterations: usize, engine: &str, mut theta: FeatureVector<f32>, fen: &[String], mut seed: i32, jobs: usize) {
+pub fn tune(iterations: usize, player: &Arc<crate::Player>, mut theta: FeatureVector<f32>, fen: &[crate::openings::Opening], mut seed: i32, jobs: usize, config: crate::GameConfig) {
+    let config = Arc::new(config);
     let ua = iterations as f32 * 0.08;
     let la = 0.1 * (ua + 1.0).powf(ALPHA) / MAGNITUDE;
 
+    let mut pool = Pool::new(jobs);
+
     for ki in 1..=iterations {
         let k = ki as f32;
 
@@ -27,7 +31,7 @@ pub fn tune(iterations: usize, engine: &str, mut theta: FeatureVector<f32>, fen:
         let theta_p = theta.clone() + ckd.clone();
         let theta_m = theta.clone() - ckd.clone();
 
-        theta = theta + get_result(engine, &theta_p, &theta_m, fen, jobs)._div(ckd) * ak;
+        theta = theta + get_result(&mut pool, player, &theta_p, &theta_m, fen, &config)._div(ckd) * ak;
 
         println!("\x1b[1;32mInfo:\x1b[0m iteration {ki} is done");
         println!("{theta:?}");
@@ -37,68 +41,75 @@ pub fn tune(iterations: usize, engine: &str, mut theta: FeatureVector<f32>, fen:
     }
 }
 
-fn get_result(engine: &str, a: &FeatureVector<f32>, b: &FeatureVector<f32>, fen: &[String], jobs: usize) -> f32 {
-    let a = a.into();
-    let b = b.into();
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Settings of the games played while tuning
+pub fn config(variant: crate::game::Variant) -> crate::GameConfig {
+    crate::GameConfig {
+        tc: [crate::tc::TimeControl::fischer(6000, 100); 2],
+        timemargin: 0,
+        log_dir: None,
+        handshake_timeout: HANDSHAKE_TIMEOUT,
+        move_timeout: std::time::Duration::from_secs(60),
+        fresh_engines: true,
+        ponder: false,
+        draw_claims: true,
+        variant,
+        adjudication: Default::default(),
+        tablebase: None,
+    }
+}
+
+fn get_result(pool: &mut Pool<(usize, crate::GameReport)>, player: &Arc<crate::Player>, a: &FeatureVector<f32>, b: &FeatureVector<f32>, fen: &[crate::openings::Opening], config: &Arc<crate::GameConfig>) -> f32 {
+    let a: Arc<FeatureVector<i32>> = Arc::new(a.into());
+    let b: Arc<FeatureVector<i32>> = Arc::new(b.into());
+    let engine = Arc::clone(&player.path);
 
     // let result = 0.0;
-    let game_result = Arc::new([
-        AtomicUsize::new(0),
-        AtomicUsize::new(0),
-        AtomicUsize::new(0),
-    ]); // a win | draw | b win
-
-    for f in fen.iter() {
-        let mut a_engine = crate::engine::Engine::new(engine, &f);
-        a_engine.send_features(&a);
-
-        let mut b_engine = crate::engine::Engine::new(engine, &f);
-        b_engine.send_features(&b);
-
-        let game = chess::Game::from_str(f).unwrap();
-
-        crate::play_with_engine(
-            a_engine,
-            b_engine,
-            engine.into(),
-            engine.into(),
-            None,
-            game.clone(),
-            f.as_str().into(),
-            6000,
-            100,
-            Arc::clone(&game_result),
-            false,
-            jobs
-        );
-
-        let mut a_engine = crate::engine::Engine::new(engine, &f);
-        a_engine.send_features(&a);
-
-        let mut b_engine = crate::engine::Engine::new(engine, &f);
-        b_engine.send_features(&b);
-
-        crate::play_with_engine(
-            b_engine,
-            a_engine,
-            engine.into(),
-            engine.into(),
-            None,
-            game,
-            f.as_str().into(),
-            6000,
-            100,
-            Arc::clone(&game_result),
-            true,
-            jobs
-        );
+    let mut stats = crate::stats::Stats::new();
+
+    for (i, f) in fen.iter().enumerate() {
+        for polarity in [false, true] {
+            let (a, b, engine) = (Arc::clone(&a), Arc::clone(&b), Arc::clone(&engine));
+            let player = Arc::clone(player);
+            let config = Arc::clone(config);
+            let f = Arc::new(f.clone());
+
+            pool.submit(move || {
+                let mut a_engine = player.start(&f.fen, None, config.handshake_timeout);
+                a_engine.send_features(&a);
+
+                let mut b_engine = player.start(&f.fen, None, config.handshake_timeout);
+                b_engine.send_features(&b);
+
+                if polarity {
+                    core::mem::swap(&mut a_engine, &mut b_engine);
+                }
+
+                let game = f.game(config.variant);
+
+                (i, crate::play_with_engine(
+                    &crate::pgn::game_id(),
+                    &mut a_engine,
+                    &mut b_engine,
+                    Arc::clone(&engine),
+                    Arc::clone(&engine),
+                    None,
+                    game,
+                    f,
+                    &config,
+                    polarity,
+                ))
+            });
+        }
     }
 
-    while crate::THREADS.load(Ordering::Relaxed) != 0 {
-        core::hint::spin_loop();
+    while let Some((pair, report)) = pool.recv() {
+        stats.add(pair, report.score, report.termination);
     }
 
-    let result = (2 * (game_result[0].load(Ordering::Relaxed) as isize - game_result[1].load(Ordering::Relaxed) as isize)) as f32;
+    let game_result = stats.wdl;
+    let result = (2 * (game_result[0] as isize - game_result[1] as isize)) as f32;
     result / fen.len() as f32
 }
 
@@ -118,6 +129,7 @@ impl<T> FeatureVector<T> {
 macro_rules! binaries {
     ($t: ty) => {
         impl FeatureVector<$t> {
+            #[allow(dead_code)]
             pub fn from_binary(b: &[u8]) -> Self {
                 let mut f = Vec::with_capacity(b.len() / core::mem::size_of::<$t>());
 
@@ -144,11 +156,11 @@ macro_rules! binaries {
 binaries!(f32);
 binaries!(i32);
 
-impl Into<FeatureVector<i32>> for &FeatureVector<f32> {
-    fn into(self) -> FeatureVector<i32> {
-        let mut new = FeatureVector::empty_with_capacity(self.len());
+impl From<&FeatureVector<f32>> for FeatureVector<i32> {
+    fn from(f: &FeatureVector<f32>) -> Self {
+        let mut new = FeatureVector::empty_with_capacity(f.len());
 
-        for i in self.iter() {
+        for i in f.iter() {
             new.push(i.round() as i32);
         }
 
@@ -156,11 +168,11 @@ impl Into<FeatureVector<i32>> for &FeatureVector<f32> {
     }
 }
 
-impl Into<FeatureVector<f32>> for &FeatureVector<i32> {
-    fn into(self) -> FeatureVector<f32> {
-        let mut new = FeatureVector::empty_with_capacity(self.len());
+impl From<&FeatureVector<i32>> for FeatureVector<f32> {
+    fn from(f: &FeatureVector<i32>) -> Self {
+        let mut new = FeatureVector::empty_with_capacity(f.len());
 
-        for i in self.iter() {
+        for i in f.iter() {
             new.push(*i as f32);
         }
 
@@ -239,11 +251,12 @@ impl<T> DerefMut for FeatureVector<T> {
     }
 }
 
-fn rand(seed: &mut i32) -> i32 {
+/// xorshift32, also used to pick moves from Polyglot books
+pub fn rand(seed: &mut i32) -> i32 {
     let mut p = *seed as u32;
     p ^= p << 13;
     p ^= p >> 17;
     p ^= p << 5;
     *seed = p as i32;
-    return *seed;
+    *seed
 }